@@ -15,20 +15,28 @@ def sh(command):
     return out.stdout
 
 
-def main(count: int, app: str):
+def main(count: int, app: str, two_way: bool):
+    nodes = []
     for i in range(count):
-        sh(f'mkfifo /tmp/netchat-fifo-{i}')
-
-    for i in range(count):
-        j = (i + 1) % count
-        sh(
-            [
-                'x-terminal-emulator',
-                '-e',
-                app.format(IN=f"/tmp/netchat-fifo-{i}", OUT=f"/tmp/netchat-fifo-{j}"),
-            ]
+        if two_way:
+            # Linked both ways to its two neighbours, the n-th input and the
+            # n-th output going to the same one
+            sources = targets = sorted({(i - 1) % count, (i + 1) % count})
+        else:
+            # One-way ring: node i reads from node i - 1 and writes to node i + 1
+            sources, targets = [(i - 1) % count], [(i + 1) % count]
+        nodes.append(
+            [(f"/tmp/netchat-fifo-{s}-{i}", f"/tmp/netchat-fifo-{i}-{t}") for s, t in zip(sources, targets)]
         )
 
+    for links in nodes:
+        for _, outgoing in links:
+            sh(f'mkfifo {outgoing}')
+
+    for links in nodes:
+        args = ' '.join(f"--input {incoming} --output {outgoing}" for incoming, outgoing in links)
+        sh(['x-terminal-emulator', '-e', app.format(LINKS=args)])
+
 
 if __name__ == '__main__':
     p = ArgumentParser(
@@ -40,9 +48,14 @@ if __name__ == '__main__':
     )
     p.add_argument(
         '--app',
-        default="cargo run -- --input {IN} --output {OUT}",
-        help="The application to be launched",
+        default="cargo run -- {LINKS}",
+        help="The application to be launched, {LINKS} being replaced by its inputs and outputs",
+    )
+    p.add_argument(
+        '--two-way',
+        action='store_true',
+        help="Link each node both ways to its two neighbours instead of building a one-way ring",
     )
     args = p.parse_args()
     assert args.count >= 2, "The number of nodes must be >= 2"
-    main(args.count, args.app)
+    main(args.count, args.app, args.two_way)
@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+
+use crate::server::messages::Channel;
+
+use super::{MAX_REACTION_LEN, SHORTCODES};
+
+/// Action typed in the input box, starting with `/`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Nickname or id of the recipient and text of a private message
+    Msg(String, String),
+    Nick(String),
+    Join(Channel),
+    /// Channel to leave, the current one if none
+    Part(Option<Channel>),
+    /// New content of the last public message of the user
+    Edit(String),
+    Delete,
+    /// Emoji to react with, shortcodes already replaced
+    React(String),
+    /// File to send, with the nickname or id of its recipient if any
+    SendFile(PathBuf, Option<String>),
+    Clock,
+    Snapshot,
+    Keys,
+    Who,
+    Help,
+    Quit,
+}
+
+/// Usage and description of every command, listed by `/help`
+pub const COMMANDS: &[(&str, &str)] = &[
+    (
+        "/msg NAME TEXT",
+        "send a private message to a nickname or id",
+    ),
+    ("/nick NAME", "change your nickname"),
+    ("/join #CHANNEL", "join a channel and display it"),
+    (
+        "/part [#CHANNEL]",
+        "leave a channel, the current one by default",
+    ),
+    (
+        "/edit TEXT",
+        "replace your last message in the current channel",
+    ),
+    ("/delete", "delete your last message in the current channel"),
+    (
+        "/react EMOJI",
+        "react to the selected message, or the latest one",
+    ),
+    (
+        "/send-file PATH [NAME]",
+        "send a file to a nickname or id, or to everyone",
+    ),
+    ("/clock", "display the vector clock"),
+    (
+        "/snapshot",
+        "save a snapshot of every message sent by every site",
+    ),
+    ("/keys", "display the fingerprints of the keys"),
+    ("/who", "list the connected users"),
+    ("/help", "list the commands"),
+    ("/quit", "exit"),
+];
+
+/// Channel name from a `/join` or `/part` argument, the leading `#` is optional
+fn channel_name(name: &str) -> Option<Channel> {
+    let name = name.trim().trim_start_matches('#');
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        None
+    } else {
+        Some(format!("#{}", name))
+    }
+}
+
+/// Splits the first word of `args` from the rest, both trimmed
+fn first_word(args: &str) -> (&str, &str) {
+    let args = args.trim();
+    match args.find(char::is_whitespace) {
+        Some(index) => (&args[..index], args[index..].trim()),
+        None => (args, ""),
+    }
+}
+
+/// Parses a line of the input box starting with `/`,
+/// fails with a message for the user if the command is unknown or misused
+pub fn parse(input: &str) -> Result<Command, String> {
+    let (name, args) = first_word(input);
+    let usage = || {
+        let usage = COMMANDS
+            .iter()
+            .find(|(usage, _)| usage.split(' ').next() == Some(name))
+            .map_or(name, |(usage, _)| usage);
+        format!("Usage: {}", usage)
+    };
+
+    let command = match name {
+        "/msg" => match first_word(args) {
+            (recipient, text) if !recipient.is_empty() && !text.is_empty() => {
+                Command::Msg(recipient.to_owned(), text.to_owned())
+            }
+            _ => return Err(usage()),
+        },
+        "/nick" if !args.is_empty() && !args.contains('\n') => Command::Nick(args.to_owned()),
+        "/join" => match channel_name(args) {
+            Some(channel) => Command::Join(channel),
+            None if args.is_empty() => return Err(usage()),
+            None => return Err(format!("Invalid channel name: {}", args)),
+        },
+        "/part" if args.is_empty() => Command::Part(None),
+        "/part" => match channel_name(args) {
+            Some(channel) => Command::Part(Some(channel)),
+            None => return Err(format!("Invalid channel name: {}", args)),
+        },
+        "/edit" if !args.is_empty() => Command::Edit(args.to_owned()),
+        "/react" if !args.is_empty() => {
+            let emoji = SHORTCODES
+                .iter()
+                .find(|(shortcode, _)| *shortcode == args)
+                .map_or(args, |(_, emoji)| emoji);
+            if emoji.chars().count() > MAX_REACTION_LEN {
+                return Err(format!(
+                    "Reactions are limited to {} characters",
+                    MAX_REACTION_LEN
+                ));
+            }
+            Command::React(emoji.to_owned())
+        }
+        "/send-file" => match first_word(args) {
+            ("", _) => return Err(usage()),
+            (path, "") => Command::SendFile(PathBuf::from(path), None),
+            (path, recipient) => Command::SendFile(PathBuf::from(path), Some(recipient.to_owned())),
+        },
+        "/delete" | "/clock" | "/snapshot" | "/keys" | "/who" | "/help" | "/quit"
+            if !args.is_empty() =>
+        {
+            return Err(usage())
+        }
+        "/delete" => Command::Delete,
+        "/clock" => Command::Clock,
+        "/snapshot" => Command::Snapshot,
+        "/keys" => Command::Keys,
+        "/who" => Command::Who,
+        "/help" => Command::Help,
+        "/quit" => Command::Quit,
+        _ if COMMANDS
+            .iter()
+            .any(|(usage, _)| usage.split(' ').next() == Some(name)) =>
+        {
+            return Err(usage())
+        }
+        _ => return Err(format!("Unknown command {}, type /help for the list", name)),
+    };
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_parsing() {
+        assert_eq!(
+            parse("/msg bob  I like trains !"),
+            Ok(Command::Msg("bob".to_owned(), "I like trains !".to_owned()))
+        );
+        assert_eq!(
+            parse("/join general"),
+            Ok(Command::Join("#general".to_owned()))
+        );
+        assert_eq!(parse("/part"), Ok(Command::Part(None)));
+        assert_eq!(parse("/react :tada:"), Ok(Command::React("🎉".to_owned())));
+        assert_eq!(
+            parse("/send-file trains.log bob"),
+            Ok(Command::SendFile(
+                PathBuf::from("trains.log"),
+                Some("bob".to_owned())
+            ))
+        );
+        assert_eq!(parse(" /quit "), Ok(Command::Quit));
+
+        assert_eq!(parse("/msg bob"), Err("Usage: /msg NAME TEXT".to_owned()));
+        assert_eq!(parse("/nick"), Err("Usage: /nick NAME".to_owned()));
+        assert_eq!(parse("/who is there"), Err("Usage: /who".to_owned()));
+        assert_eq!(
+            parse("/join #a b"),
+            Err("Invalid channel name: #a b".to_owned())
+        );
+        assert!(parse("/trains").is_err());
+    }
+}
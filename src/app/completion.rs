@@ -0,0 +1,90 @@
+use super::commands::COMMANDS;
+
+/// Candidates for the word before the cursor, cycled through by pressing Tab again
+#[derive(Debug)]
+pub struct Completion {
+    /// Byte index of the start of the word completed
+    pub start: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    /// Completions of `word`, starting at `start` in the input box, `None` if there is none.
+    /// Commands are completed at the start of the line, channels after a `#`,
+    /// nicknames and ids anywhere else
+    pub fn new<'a>(
+        start: usize,
+        word: &str,
+        channels: &[String],
+        names: impl Iterator<Item = &'a String>,
+    ) -> Option<Self> {
+        let matches = |candidate: &str| candidate.to_lowercase().starts_with(&word.to_lowercase());
+        let mut candidates: Vec<String> = if word.starts_with('/') && start == 0 {
+            COMMANDS
+                .iter()
+                .filter_map(|(usage, _)| usage.split(' ').next())
+                .filter(|command| matches(command))
+                .map(str::to_owned)
+                .collect()
+        } else if word.starts_with('#') {
+            channels
+                .iter()
+                .filter(|channel| matches(channel))
+                .cloned()
+                .collect()
+        } else {
+            names.filter(|name| matches(name)).cloned().collect()
+        };
+        candidates.sort();
+        candidates.dedup();
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(Completion {
+                start,
+                candidates,
+                index: 0,
+            })
+        }
+    }
+
+    /// Candidate offered
+    pub fn current(&self) -> &str {
+        &self.candidates[self.index]
+    }
+
+    /// Moves on to the next candidate, back to the first after the last one
+    pub fn cycle(&mut self) {
+        self.index = (self.index + 1) % self.candidates.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_completion() {
+        let channels = vec!["#general".to_owned(), "#games".to_owned()];
+        let names = [
+            "bob".to_owned(),
+            "Bertrand".to_owned(),
+            "alice".to_owned(),
+            "bob".to_owned(),
+        ];
+
+        let mut completion = Completion::new(4, "b", &channels, names.iter()).unwrap();
+        assert_eq!(completion.current(), "Bertrand");
+        completion.cycle();
+        assert_eq!(completion.current(), "bob");
+        completion.cycle();
+        assert_eq!(completion.current(), "Bertrand");
+
+        let completion = Completion::new(6, "#ga", &channels, names.iter()).unwrap();
+        assert_eq!(completion.current(), "#games");
+        let completion = Completion::new(0, "/jo", &channels, names.iter()).unwrap();
+        assert_eq!(completion.current(), "/join");
+        assert!(Completion::new(5, "/jo", &channels, names.iter()).is_none());
+    }
+}
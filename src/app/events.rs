@@ -6,7 +6,8 @@ use std::time::Duration;
 use termion::event::Key;
 use termion::input::TermRead;
 
-use crate::server::messages::Msg;
+use crate::app::{AppId, Receipt};
+use crate::server::messages::{Msg, MsgId};
 use crate::server::Clock;
 
 pub enum Event {
@@ -14,12 +15,39 @@ pub enum Event {
     UserInput(Key),
     /// Message from another app (write in a file)
     DistantMessage(Msg),
+    /// Message sent by the user, with its content in clear
+    Sent(Msg),
+    /// A private message sent by the user was delivered or read
+    Receipt(MsgId, Receipt),
+    /// Another app is writing a message
+    Typing(AppId),
+    /// A file from an app is being received: transfer id, sender, name,
+    /// number of pieces received and total
+    FileProgress(u64, AppId, String, usize, usize),
+    /// A file transfer is over, whether it succeeded or not
+    FileDone(u64),
+    /// Messages saved by a previous run
+    History(Vec<Msg>),
+    /// Messages sent before this app joined, in causal order
+    Backlog(Vec<Msg>),
     /// Information from the server
     ServerMessage(String),
+    /// Failure reported by the server
+    ServerError(String),
+    /// Nickname announced by an app (possibly this one)
+    PeerNickname(AppId, String),
+    /// The server changed our app id and nickname
+    Renamed(AppId, String),
+    /// Unrecoverable server error, the app has to exit
+    Fatal(String),
     /// Periodically send tick a to refresh the UI
     Tick,
     /// Display vector clock
     DisplayClock(Clock),
+    /// Display the apps currently connected, with their nicknames
+    DisplayPeers(Vec<(AppId, String)>),
+    /// Display public key fingerprints
+    DisplayKeys(Vec<(AppId, String)>),
 }
 
 /// A small event handler that wraps termion input and tick events. Each event
@@ -73,17 +101,12 @@ impl Events {
             let tx = tx.clone();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    match evt {
-                        Ok(key) => {
-                            if let Err(_) = tx.send(Event::UserInput(key)) {
-                                return;
-                            }
-                            if key == config.exit_key {
-                                return;
-                            }
-                        }
-                        Err(_) => {}
+                for key in stdin.keys().flatten() {
+                    if tx.send(Event::UserInput(key)).is_err() {
+                        return;
+                    }
+                    if key == config.exit_key {
+                        return;
                     }
                 }
             })
@@ -0,0 +1,216 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+/// Number of lines kept
+const MAX_ENTRIES: usize = 1000;
+
+/// Lines sent from the input box, oldest first,
+/// saved one JSON string per line if a file is given
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    file: Option<PathBuf>,
+    /// Entry recalled, `entries.len()` when writing a new line
+    position: usize,
+    /// Line being written before browsing the history
+    draft: String,
+    /// Reverse search in progress, if any
+    search: Option<Search>,
+}
+
+#[derive(Debug)]
+struct Search {
+    query: String,
+    /// Newest entry matching the query, at or before the previous match
+    found: Option<usize>,
+    /// Content of the input box when the search started
+    original: String,
+}
+
+impl History {
+    /// Reads back the lines saved in `file`, later lines are appended to it
+    pub fn open(file: PathBuf) -> Self {
+        let mut entries: Vec<String> = match fs::read_to_string(&file) {
+            Ok(content) => content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                log::error!("Could not read the input history: {}", e);
+                Vec::new()
+            }
+        };
+        let mut history = History {
+            file: Some(file),
+            ..History::default()
+        };
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            history.write(&entries, false);
+        }
+        history.position = entries.len();
+        history.entries = entries;
+        history
+    }
+
+    /// Saves `entries` to the history file, appending them or replacing its content
+    fn write(&self, entries: &[String], append: bool) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        // Lines may be private messages, only the owner may read them
+        let written = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .mode(0o600)
+            .open(file)
+            .and_then(|mut file| {
+                for entry in entries {
+                    let line = serde_json::to_string(entry)?;
+                    file.write_all(format!("{}\n", line).as_bytes())?;
+                }
+                Ok(())
+            });
+        if let Err(e) = written {
+            log::error!("Could not save the input history: {}", e);
+        }
+    }
+
+    /// Records a line sent by the user, unless empty or the same as the previous one
+    pub fn push(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.entries.last().map(String::as_str) != Some(line) {
+            self.entries.push(line.to_owned());
+            self.write(&self.entries[self.entries.len() - 1..], true);
+            if self.entries.len() > MAX_ENTRIES {
+                self.entries.remove(0);
+            }
+        }
+        self.position = self.entries.len();
+        self.draft.clear();
+    }
+
+    /// Line sent before the one recalled, `current` being kept
+    /// to come back to it once past the newest line
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        if self.position == 0 {
+            return None;
+        }
+        if self.position == self.entries.len() {
+            self.draft = current.to_owned();
+        }
+        self.position -= 1;
+        Some(&self.entries[self.position])
+    }
+
+    /// Line sent after the one recalled, or the line being written
+    pub fn next(&mut self) -> Option<&str> {
+        if self.position >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        Some(self.entries.get(self.position).unwrap_or(&self.draft))
+    }
+
+    /// Starts a reverse search from the newest line
+    pub fn start_search(&mut self, current: &str) {
+        self.search = Some(Search {
+            query: String::new(),
+            found: None,
+            original: current.to_owned(),
+        });
+    }
+
+    /// Query of the search in progress, if any
+    pub fn query(&self) -> Option<&str> {
+        self.search.as_ref().map(|search| search.query.as_str())
+    }
+
+    /// Line matching the query of the search in progress
+    pub fn found(&self) -> Option<&str> {
+        let index = self.search.as_ref()?.found?;
+        Some(&self.entries[index])
+    }
+
+    /// Updates the query, looking for a match from the current one backwards
+    pub fn edit_query(&mut self, edit: impl FnOnce(&mut String)) {
+        let entries = &self.entries;
+        if let Some(search) = &mut self.search {
+            edit(&mut search.query);
+            let end = search.found.map_or(entries.len(), |index| index + 1);
+            search.found = Self::find(&entries[..end], &search.query)
+                .or_else(|| Self::find(entries, &search.query));
+        }
+    }
+
+    /// Looks for an older line matching the query, the current match is kept if none
+    pub fn search_older(&mut self) {
+        if let Some(search) = &mut self.search {
+            if let Some(index) = search.found {
+                search.found = Self::find(&self.entries[..index], &search.query).or(Some(index));
+            }
+        }
+    }
+
+    /// Ends the search, returning the line found if accepted,
+    /// or else the content of the input box when the search started
+    pub fn end_search(&mut self, accept: bool) -> Option<String> {
+        let search = self.search.take()?;
+        match search.found {
+            Some(index) if accept => Some(self.entries[index].clone()),
+            _ => Some(search.original),
+        }
+    }
+
+    /// Index of the newest entry containing `query`
+    fn find(entries: &[String], query: &str) -> Option<usize> {
+        if query.is_empty() {
+            return None;
+        }
+        entries.iter().rposition(|entry| entry.contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_recall() {
+        let file = std::env::temp_dir().join(format!("netchat-history-{}", std::process::id()));
+        let mut history = History::open(file.clone());
+        for line in &["I like trains", "/nick bob", "/nick bob", "", "choo choo"] {
+            history.push(line);
+        }
+
+        assert_eq!(history.previous("draft"), Some("choo choo"));
+        assert_eq!(history.previous("choo choo"), Some("/nick bob"));
+        assert_eq!(history.previous("/nick bob"), Some("I like trains"));
+        assert_eq!(history.previous("I like trains"), None);
+        assert_eq!(history.next(), Some("/nick bob"));
+        assert_eq!(history.next(), Some("choo choo"));
+        assert_eq!(history.next(), Some("draft"));
+        assert_eq!(history.next(), None);
+
+        history.start_search("draft");
+        history.edit_query(|query| query.push_str("ch"));
+        assert_eq!(history.found(), Some("choo choo"));
+        history.search_older();
+        assert_eq!(history.found(), Some("choo choo"));
+        history.edit_query(String::clear);
+        history.edit_query(|query| query.push_str("trains"));
+        assert_eq!(history.found(), Some("I like trains"));
+        assert_eq!(history.end_search(false), Some("draft".to_owned()));
+        assert_eq!(history.query(), None);
+
+        // Saved between runs
+        let mut history = History::open(file.clone());
+        assert_eq!(history.previous(""), Some("choo choo"));
+        fs::remove_file(file).unwrap();
+    }
+}
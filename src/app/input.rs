@@ -0,0 +1,283 @@
+use shrinkwraprs::Shrinkwrap;
+use unicode_width::UnicodeWidthChar;
+
+/// Content of the input box, with the position of the cursor in it.
+/// The content may span several lines
+#[derive(Shrinkwrap, Debug, Default)]
+pub struct Input {
+    #[shrinkwrap(main_field)]
+    text: String,
+    /// Byte index of the cursor, always on a char boundary
+    cursor: usize,
+    /// Index of the first char displayed in each line when the text is wider than the box
+    scroll: usize,
+    /// Index of the first line displayed when there are more lines than the box can hold
+    top: usize,
+}
+
+impl Input {
+    /// Inserts a char at the cursor and moves past it
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Removes the char before the cursor
+    pub fn backspace(&mut self) {
+        let start = self.previous(self.cursor);
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Removes the char under the cursor
+    pub fn delete(&mut self) {
+        let end = self.next(self.cursor);
+        self.text.drain(self.cursor..end);
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.previous(self.cursor);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = self.next(self.cursor);
+    }
+
+    /// Moves the cursor to the start of its line
+    pub fn home(&mut self) {
+        self.cursor = self.line_start(self.cursor);
+    }
+
+    /// Moves the cursor to the end of its line
+    pub fn end(&mut self) {
+        self.cursor = self.line_end(self.cursor);
+    }
+
+    /// Moves the cursor to the previous line, fails if it is on the first one
+    pub fn up(&mut self) -> bool {
+        let start = self.line_start(self.cursor);
+        if start == 0 {
+            return false;
+        }
+        let column = self.text[start..self.cursor].chars().count();
+        self.cursor = self.line_index(self.line_start(start - 1), column);
+        true
+    }
+
+    /// Moves the cursor to the next line, fails if it is on the last one
+    pub fn down(&mut self) -> bool {
+        let end = self.line_end(self.cursor);
+        if end == self.text.len() {
+            return false;
+        }
+        let column = self.text[self.line_start(self.cursor)..self.cursor]
+            .chars()
+            .count();
+        self.cursor = self.line_index(end + 1, column);
+        true
+    }
+
+    /// Number of lines of the content
+    pub fn line_count(&self) -> usize {
+        self.text.split('\n').count()
+    }
+
+    /// Moves the cursor to the start of the current or previous word
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    /// Moves the cursor to the end of the current or next word
+    pub fn word_right(&mut self) {
+        let rest = &self.text[self.cursor..];
+        let word = rest.len() - rest.trim_start().len();
+        self.cursor += rest[word..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |end| word + end);
+    }
+
+    /// Removes the word before the cursor
+    pub fn kill_word(&mut self) {
+        let start = self.word_start();
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Byte index of the start of the word before the cursor, and that word
+    pub fn word_before_cursor(&self) -> (usize, &str) {
+        let start = self.text[..self.cursor]
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| {
+                i + self.text[i..].chars().next().map_or(1, char::len_utf8)
+            });
+        (start, &self.text[start..self.cursor])
+    }
+
+    /// Replaces the text from `start` to the cursor with `text`
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        self.text.replace_range(start..self.cursor, text);
+        self.cursor = start + text.len();
+    }
+
+    /// Removes everything before the cursor on its line
+    pub fn kill_line(&mut self) {
+        let start = self.line_start(self.cursor);
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Replaces the content, the cursor is put at the end
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+        self.scroll = 0;
+        self.top = 0;
+    }
+
+    /// Empties the input box, returning its content
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        self.scroll = 0;
+        self.top = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    /// Part of the content fitting in `width` columns and `height` lines,
+    /// with the column and line of the cursor in it,
+    /// scrolling to keep the cursor visible
+    pub fn view(&mut self, width: usize, height: usize) -> (Vec<String>, usize, usize) {
+        let start = self.line_start(self.cursor);
+        let row = self.text[..start].matches('\n').count();
+        let chars: Vec<char> = self.text[start..self.line_end(self.cursor)]
+            .chars()
+            .collect();
+        let cursor = self.text[start..self.cursor].chars().count();
+        let columns = |chars: &[char]| -> usize { chars.iter().filter_map(|c| c.width()).sum() };
+
+        self.scroll = self.scroll.min(cursor);
+        // The cursor needs a column of its own
+        while self.scroll < cursor && columns(&chars[self.scroll..cursor]) >= width {
+            self.scroll += 1;
+        }
+        self.top = self.top.min(row).max((row + 1).saturating_sub(height));
+
+        let lines = self
+            .text
+            .split('\n')
+            .skip(self.top)
+            .take(height)
+            .map(|line| {
+                let mut visible = String::new();
+                let mut used = 0;
+                for c in line.chars().skip(self.scroll) {
+                    used += c.width().unwrap_or(0);
+                    if used > width {
+                        break;
+                    }
+                    visible.push(c);
+                }
+                visible
+            })
+            .collect();
+        (lines, columns(&chars[self.scroll..cursor]), row - self.top)
+    }
+
+    /// Byte index of the start of the line containing `index`
+    fn line_start(&self, index: usize) -> usize {
+        self.text[..index].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    /// Byte index of the end of the line containing `index`
+    fn line_end(&self, index: usize) -> usize {
+        self.text[index..]
+            .find('\n')
+            .map_or(self.text.len(), |i| index + i)
+    }
+
+    /// Byte index of the `column`-th char of the line starting at `start`,
+    /// or of its end if it is shorter
+    fn line_index(&self, start: usize, column: usize) -> usize {
+        let end = self.line_end(start);
+        self.text[start..end]
+            .char_indices()
+            .nth(column)
+            .map_or(end, |(i, _)| start + i)
+    }
+
+    /// Byte index of the char before `index`
+    fn previous(&self, index: usize) -> usize {
+        self.text[..index]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// Byte index of the char after `index`
+    fn next(&self, index: usize) -> usize {
+        self.text[index..]
+            .chars()
+            .next()
+            .map_or(index, |c| index + c.len_utf8())
+    }
+
+    /// Byte index of the start of the word before the cursor
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before.rfind(char::is_whitespace).map_or(0, |i| {
+            i + before[i..].chars().next().map_or(1, char::len_utf8)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_editing() {
+        let mut input = Input::default();
+        "I trains".chars().for_each(|c| input.insert(c));
+        input.word_left();
+        "like ".chars().for_each(|c| input.insert(c));
+        input.end();
+        input.insert('!');
+        assert_eq!(input.as_str(), "I like trains!");
+
+        input.home();
+        input.word_right();
+        input.word_right();
+        input.kill_word();
+        assert_eq!(input.as_str(), "I  trains!");
+        input.delete();
+        input.left();
+        input.backspace();
+        assert_eq!(input.as_str(), " trains!");
+        input.right();
+        input.kill_line();
+        assert_eq!(input.as_str(), "trains!");
+
+        input.take();
+        "électrique 🚂 train".chars().for_each(|c| input.insert(c));
+        assert_eq!(input.view(8, 1), (vec![" train".to_owned()], 6, 0));
+        input.home();
+        assert_eq!(input.view(8, 1), (vec!["électriq".to_owned()], 0, 0));
+
+        // Lines are edited separately
+        input.end();
+        "\nchoo\nchoo".chars().for_each(|c| input.insert(c));
+        assert_eq!(input.line_count(), 3);
+        assert_eq!(
+            input.view(8, 2),
+            (vec!["choo".to_owned(), "choo".to_owned()], 4, 1)
+        );
+        assert!(input.up());
+        assert!(input.up());
+        assert!(!input.up());
+        assert!(input.down());
+        input.home();
+        input.insert('>');
+        input.kill_line();
+        assert_eq!(input.take(), "électrique 🚂 train\nchoo\nchoo");
+        assert!(input.is_empty());
+    }
+}
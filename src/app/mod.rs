@@ -1,8 +1,14 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::{self, Write};
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
 use unicode_width::UnicodeWidthStr;
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, LocalResult, TimeZone};
+
 use termion::cursor::Goto;
 use termion::event::Key;
 use termion::input::MouseTerminal;
@@ -18,25 +24,97 @@ use tui::Terminal;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
+pub mod commands;
+use commands::{Command, COMMANDS};
+
+pub mod completion;
+use completion::Completion;
+
 pub mod events;
 use events::{Event, Events};
 
+pub mod history;
+use history::History;
+
+pub mod input;
+use input::Input;
+
+pub mod theme;
+use theme::Theme;
+
+pub mod wrap;
+use wrap::wrap;
+
 use crate::server::events::Event as ServerEvent;
-use crate::server::messages::Header::{Private, Public};
+use crate::server::messages::Header::{Delete, Edit, Group, Private, Public, Reaction};
+use crate::server::messages::{Channel, Date, GroupId, Msg, MsgId, DEFAULT_CHANNEL};
 
 pub type AppId = String;
 
+/// Minimum delay between two typing notifications sent to the other apps
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+/// How long another app is shown as typing after its last notification
+const TYPING_DISPLAY: Duration = Duration::from_secs(4);
+/// Appended to the messages created before a message they follow, according to their timestamps
+const CLOCK_SKEW_NOTE: &str = " (sent before the message it follows, a clock is off)";
+/// Number of lines the input box grows to before scrolling
+const MAX_INPUT_LINES: usize = 5;
+/// Maximum number of characters of a reaction
+const MAX_REACTION_LEN: usize = 16;
+/// Emojis sent for the usual shortcodes, other reactions are sent as typed
+const SHORTCODES: &[(&str, &str)] = &[
+    (":+1:", "👍"),
+    (":-1:", "👎"),
+    (":heart:", "❤"),
+    (":smile:", "😄"),
+    (":laughing:", "😆"),
+    (":tada:", "🎉"),
+    (":eyes:", "👀"),
+    (":thinking:", "🤔"),
+];
+
+/// Progress of a private message sent by the user
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Receipt {
+    Sent,
+    Delivered,
+    Read,
+}
+
+/// Destination of the messages sent with Ctrl+p
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recipient {
+    App(AppId),
+    /// Group id and members, this app included
+    Group(GroupId, Vec<AppId>),
+}
+
 pub enum Message {
     System(String),
-    User(String),
+    /// Failure reported to the user
+    Error(String),
+    /// Private or group message, with its sender
+    User(AppId, String),
+    /// Public message and its sender, which may edit or delete it later
+    Posted(MsgId, AppId, String),
+    /// Quote of the message answered by the following one
+    Quote(String),
+    /// Private message sent by the user, followed by its receipt marker
+    Outgoing(String, MsgId, Receipt),
 }
 use Message::*;
 
 impl Message {
-    pub fn str(&self) -> &str {
+    pub fn str(&self) -> Cow<'_, str> {
         match self {
-            System(s) => s,
-            User(s) => s,
+            System(s) => Cow::from(s.as_str()),
+            Error(s) => Cow::from(s.as_str()),
+            User(_, s) => Cow::from(s.as_str()),
+            Posted(_, _, s) => Cow::from(s.as_str()),
+            Quote(s) => Cow::from(s.as_str()),
+            Outgoing(s, _, Receipt::Sent) => Cow::from(s.as_str()),
+            Outgoing(s, _, Receipt::Delivered) => Cow::from(format!("{} ✓", s)),
+            Outgoing(s, _, Receipt::Read) => Cow::from(format!("{} ✓✓", s)),
         }
     }
 }
@@ -45,29 +123,612 @@ impl Message {
 pub struct App {
     //Application id
     pub id: AppId,
-    /// History of received/sent messages
-    pub messages: Vec<Message>,
+    /// Name displayed to the other users
+    pub nickname: String,
+    /// Known nicknames of the other apps
+    nicknames: HashMap<AppId, String>,
+    /// Joined channels, in the order they were joined
+    channels: Vec<Channel>,
+    /// Channel displayed, where public messages are sent
+    channel: Channel,
+    /// History of received/sent messages of each joined channel,
+    /// the other messages are shown in the current one
+    pub messages: HashMap<Channel, Vec<Message>>,
+    /// Channels with messages the user has not seen yet
+    active_channels: HashSet<Channel>,
+    /// Public messages of the user which can still be edited or deleted
+    own_posts: HashSet<MsgId>,
+    /// Public message selected to be answered
+    selected: Option<MsgId>,
+    /// Reactions to public messages, in order of appearance, with the apps which sent them
+    reactions: HashMap<MsgId, Vec<(String, Vec<AppId>)>>,
     /// Current value of the input box
-    input: String,
-    /// Id of the first message to be displayed, used for scrolling
-    first_display_message_id: usize,
-    /// Id of the private message recipient
-    private_recipient_id: AppId,
+    input: Input,
+    /// Lines sent from the input box
+    pub history: History,
+    /// Candidates for the word being completed, while Tab is pressed
+    completion: Option<Completion>,
+    /// Number of lines hidden above the list, counted from the latest message, used for scrolling
+    first_display_line: usize,
+    /// Recipient of the private messages
+    private_recipient: Recipient,
+    /// Private messages received since the user last pressed a key, with their senders
+    unread: Vec<(AppId, MsgId)>,
+    /// `strftime` format of the time displayed before messages, none if empty
+    pub time_format: String,
+    /// Timestamps of the messages of each app, by date of their sender
+    sent_timestamps: HashMap<AppId, BTreeMap<Date, u64>>,
+    /// Timestamps of the public messages, which may be answered
+    timestamps: HashMap<MsgId, u64>,
+    /// Apps currently writing a message, with the date of their last notification
+    typing: HashMap<AppId, Instant>,
+    /// Date of the last typing notification sent
+    last_typing_sent: Option<Instant>,
+    /// Files being received: sender, name, pieces received and total, by transfer id
+    transfers: HashMap<u64, (AppId, String, usize, usize)>,
+    /// Styles of the messages
+    pub theme: Theme,
 }
 
 impl Default for App {
     fn default() -> App {
         let mut rng = thread_rng();
+        let id: AppId = (0..8).map(|_| rng.sample(Alphanumeric)).collect();
         App {
-            id: (0..8).map(|_| rng.sample(Alphanumeric)).collect(),
-            input: String::new(),
-            messages: Vec::new(),
-            first_display_message_id: 0,
-            private_recipient_id: "no one".to_owned(),
+            nickname: id.clone(),
+            nicknames: HashMap::new(),
+            id,
+            input: Input::default(),
+            history: History::default(),
+            completion: None,
+            channels: vec![DEFAULT_CHANNEL.to_owned()],
+            channel: DEFAULT_CHANNEL.to_owned(),
+            messages: HashMap::new(),
+            active_channels: HashSet::new(),
+            own_posts: HashSet::new(),
+            selected: None,
+            reactions: HashMap::new(),
+            first_display_line: 0,
+            private_recipient: Recipient::App("no one".to_owned()),
+            unread: Vec::new(),
+            time_format: "%H:%M".to_owned(),
+            sent_timestamps: HashMap::new(),
+            timestamps: HashMap::new(),
+            typing: HashMap::new(),
+            last_typing_sent: None,
+            transfers: HashMap::new(),
+            theme: Theme::default(),
         }
     }
 }
 
+impl App {
+    /// Nickname of an app if known, its id otherwise
+    pub fn display_name(&self, id: &str) -> String {
+        if id == self.id {
+            return self.nickname.clone();
+        }
+        self.nicknames
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_owned())
+    }
+
+    /// Names of the members of a group other than this app
+    fn group_name(&self, members: &[AppId]) -> String {
+        members
+            .iter()
+            .filter(|id| **id != self.id)
+            .map(|id| self.display_name(id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Line displayed for a public, private or group message, preceded by its time
+    fn message_line(&self, msg: &Msg) -> Option<String> {
+        let time = match msg.timestamp {
+            Some(timestamp) if !self.time_format.is_empty() => {
+                format!("[{}] ", format_time(timestamp, &self.time_format))
+            }
+            _ => String::new(),
+        };
+        let line = match &msg.header {
+            Public(_, content) if msg.sender_id == self.id => Some(format!("You: {}", content)),
+            Public(_, content) => Some(format!(
+                "{}: {}",
+                self.display_name(&msg.sender_id),
+                content
+            )),
+            Private(app_id, content) if msg.sender_id == self.id => {
+                Some(format!("You to {}: {}", self.display_name(app_id), content))
+            }
+            Private(_, content) => Some(format!(
+                "{} to You: {}",
+                self.display_name(&msg.sender_id),
+                content
+            )),
+            // Decrypted group messages only hold our own copy
+            Group(_, members, contents) => {
+                let sender = if msg.sender_id == self.id {
+                    "You".to_owned()
+                } else {
+                    self.display_name(&msg.sender_id)
+                };
+                Some(format!(
+                    "[group {}] {}: {}",
+                    self.group_name(members),
+                    sender,
+                    contents.get(&self.id)?
+                ))
+            }
+            _ => None,
+        };
+        line.map(|line| time + &line)
+    }
+
+    /// Records the timestamp of a message, tells whether it is earlier than the one
+    /// of a message it causally follows: a message its sender sent before, according
+    /// to the date of the sender in their clocks, or the message it answers.
+    /// Messages may be shown in any order, as history and backlog are replayed
+    fn out_of_order(&mut self, msg: &Msg) -> bool {
+        let timestamp = match msg.timestamp {
+            Some(timestamp) => timestamp,
+            None => return false,
+        };
+        if let Public(_, _) = msg.header {
+            self.timestamps.insert(msg.id, timestamp);
+        }
+        let parent = msg
+            .reply_to
+            .and_then(|parent| self.timestamps.get(&parent).copied());
+        let date = msg.clock.get(&msg.sender_id).copied().unwrap_or(0);
+        let sent = self
+            .sent_timestamps
+            .entry(msg.sender_id.clone())
+            .or_default();
+        let previous = sent.range(..date).map(|(_, sent)| *sent).max();
+        sent.insert(date, timestamp);
+        previous.is_some_and(|previous| timestamp < previous)
+            || parent.is_some_and(|parent| timestamp < parent)
+    }
+
+    /// Shows a message in the current channel
+    pub fn push(&mut self, message: Message) {
+        let channel = self.channel.clone();
+        self.push_to(&channel, message);
+    }
+
+    fn push_to(&mut self, channel: &str, message: Message) {
+        if channel != self.channel {
+            self.active_channels.insert(channel.to_owned());
+        }
+        self.messages
+            .entry(channel.to_owned())
+            .or_default()
+            .push(message);
+    }
+
+    /// Shows a message sent, received or restored: public messages in their channel,
+    /// revisions by updating the line of the message they apply to
+    fn show(&mut self, msg: &Msg, prefix: &str) {
+        match &msg.header {
+            Edit(target, content) => {
+                self.revise(msg, *target, &format!("{} (edited)", content));
+            }
+            Delete(target) => {
+                self.own_posts.remove(target);
+                self.revise(msg, *target, "[deleted]");
+            }
+            Reaction(target, emoji) => {
+                let emoji: String = emoji.chars().take(MAX_REACTION_LEN).collect();
+                let reactions = self.reactions.entry(*target).or_default();
+                match reactions.iter_mut().find(|(e, _)| *e == emoji) {
+                    Some((_, senders)) if senders.contains(&msg.sender_id) => {}
+                    Some((_, senders)) => senders.push(msg.sender_id.clone()),
+                    None => reactions.push((emoji, vec![msg.sender_id.clone()])),
+                }
+            }
+            Public(channel, _) => {
+                if let Some(line) = self.message_line(msg) {
+                    if msg.sender_id == self.id {
+                        self.own_posts.insert(msg.id);
+                    }
+                    let note = if self.out_of_order(msg) {
+                        CLOCK_SKEW_NOTE
+                    } else {
+                        ""
+                    };
+                    self.push_to(
+                        channel,
+                        Posted(
+                            msg.id,
+                            msg.sender_id.clone(),
+                            format!("{}{}{}", prefix, line, note),
+                        ),
+                    );
+                    // Pushed after the answer since the latest messages are displayed first
+                    if let Some(parent) = msg.reply_to {
+                        let quote = self
+                            .post_text(parent)
+                            .unwrap_or("a message which was not received")
+                            .to_owned();
+                        self.push_to(channel, Quote(format!("╭ {}", quote)));
+                    }
+                }
+            }
+            _ => {
+                if let Some(line) = self.message_line(msg) {
+                    let note = if self.out_of_order(msg) {
+                        CLOCK_SKEW_NOTE
+                    } else {
+                        ""
+                    };
+                    self.push(User(
+                        msg.sender_id.clone(),
+                        format!("{}{}{}", prefix, line, note),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Replaces the line of the public message `target` with `content`,
+    /// from the sender of `revision`
+    fn revise(&mut self, revision: &Msg, target: MsgId, content: &str) {
+        let mut revised = revision.clone();
+        revised.header = Public(String::new(), content.to_owned());
+        let line = match self.message_line(&revised) {
+            Some(line) => line,
+            None => return,
+        };
+        for message in self.messages.values_mut().flat_map(|m| m.iter_mut()) {
+            if let Posted(id, _, text) = message {
+                if *id == target {
+                    *text = line;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Text displayed for a message, followed by the reactions to it
+    fn display_line<'a>(&self, message: &'a Message) -> Cow<'a, str> {
+        match message {
+            Posted(id, _, text) if self.reactions.contains_key(id) => {
+                let reactions: Vec<String> = self.reactions[id]
+                    .iter()
+                    .map(|(emoji, senders)| format!("{} {}", emoji, senders.len()))
+                    .collect();
+                Cow::from(format!("{}  [{}]", text, reactions.join(" ")))
+            }
+            _ => message.str(),
+        }
+    }
+
+    /// Style of the lines of a message, according to its kind and sender
+    fn style(&self, message: &Message) -> Style {
+        let style = match message {
+            System(_) => self.theme.system,
+            Error(_) => self.theme.error,
+            Quote(_) => self.theme.quote,
+            Posted(_, sender, _) => self.sender_style(self.theme.public, sender),
+            User(sender, _) => self.sender_style(self.theme.private, sender),
+            Outgoing(_, _, _) => self.sender_style(self.theme.private, &self.id),
+        };
+        match message {
+            Posted(id, _, _) if self.selected == Some(*id) => {
+                style.modifier(style.modifier | Modifier::REVERSED)
+            }
+            _ => style,
+        }
+    }
+
+    /// `style` in the colour of `sender`, or the style of the user's own messages
+    /// keeping the modifiers of `style`
+    fn sender_style(&self, style: Style, sender: &str) -> Style {
+        if sender == self.id {
+            self.theme
+                .own
+                .modifier(self.theme.own.modifier | style.modifier)
+        } else {
+            style.fg(self.theme.sender_color(sender))
+        }
+    }
+
+    /// Lines of at most `width` columns a message is displayed on
+    fn wrapped_lines(&self, message: &Message, width: usize) -> Vec<String> {
+        wrap(&self.display_line(message), width)
+    }
+
+    /// Latest public message of the current channel
+    fn last_post(&self) -> Option<MsgId> {
+        self.current_messages()
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                Posted(id, _, _) => Some(*id),
+                _ => None,
+            })
+    }
+
+    /// Line of a public message
+    fn post_text(&self, msg_id: MsgId) -> Option<&str> {
+        self.messages
+            .values()
+            .flat_map(|m| m.iter())
+            .find_map(|message| match message {
+                Posted(id, _, text) if *id == msg_id => Some(text.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Moves the selection to an older or newer public message of the current channel,
+    /// starting from the latest one, and scrolls to keep it in the `list_size` lines
+    /// of `list_width` columns displayed
+    fn move_selection(&mut self, older: bool, list_width: usize, list_size: usize) {
+        // Latest first, along with their first line and number of lines in the displayed list
+        let mut line = 0;
+        let posts: Vec<(usize, usize, MsgId)> = self
+            .current_messages()
+            .iter()
+            .rev()
+            .filter_map(|message| {
+                let height = self.wrapped_lines(message, list_width).len();
+                line += height;
+                match message {
+                    Posted(id, _, _) => Some((line - height, height, *id)),
+                    _ => None,
+                }
+            })
+            .collect();
+        let current = self
+            .selected
+            .and_then(|selected| posts.iter().position(|(_, _, id)| *id == selected));
+        let next = match current {
+            None => 0,
+            Some(index) if older => (index + 1).min(posts.len() - 1),
+            Some(index) => index.saturating_sub(1),
+        };
+        if let Some((start, height, id)) = posts.get(next) {
+            self.selected = Some(*id);
+            if *start < self.first_display_line {
+                self.first_display_line = *start;
+            } else if start + height > self.first_display_line + list_size {
+                self.first_display_line = (start + height).saturating_sub(list_size).min(*start);
+            }
+        }
+    }
+
+    /// Last public message of the user in the current channel which can be changed
+    fn last_own_post(&self) -> Option<MsgId> {
+        self.current_messages()
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                Posted(id, _, _) if self.own_posts.contains(id) => Some(*id),
+                _ => None,
+            })
+    }
+
+    /// Messages of the current channel
+    fn current_messages(&self) -> &[Message] {
+        self.messages
+            .get(&self.channel)
+            .map_or(&[], |messages| messages)
+    }
+
+    fn switch_to(&mut self, channel: Channel) {
+        self.active_channels.remove(&channel);
+        self.channel = channel;
+        self.first_display_line = 0;
+        self.selected = None;
+    }
+
+    /// Joined channels, the current one between brackets
+    /// and those with unseen messages marked with a star
+    fn channel_list(&self) -> String {
+        self.channels
+            .iter()
+            .map(|channel| {
+                if *channel == self.channel {
+                    format!("[{}]", channel)
+                } else if self.active_channels.contains(channel) {
+                    format!("{}*", channel)
+                } else {
+                    channel.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Updates the marker of a private message sent by the user,
+    /// receipts may arrive out of order so the marker never goes back
+    fn set_receipt(&mut self, msg_id: MsgId, receipt: Receipt) {
+        for message in self.messages.values_mut().flat_map(|m| m.iter_mut().rev()) {
+            if let Outgoing(_, id, current) = message {
+                if *id == msg_id {
+                    if receipt > *current {
+                        *current = receipt;
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Lets the other apps know that the user is writing, at most every `TYPING_INTERVAL`
+    fn notify_typing(&mut self, server_tx: &mpsc::Sender<ServerEvent>) {
+        if self.input.is_empty()
+            || self
+                .last_typing_sent
+                .is_some_and(|sent| sent.elapsed() < TYPING_INTERVAL)
+        {
+            return;
+        }
+        self.last_typing_sent = Some(Instant::now());
+        send_to_server(ServerEvent::UserTyping, server_tx);
+    }
+
+    /// Status line listing the apps currently writing
+    fn typing_status(&self) -> String {
+        let mut names: Vec<String> = self
+            .typing
+            .iter()
+            .filter(|(_, since)| since.elapsed() < TYPING_DISPLAY)
+            .map(|(id, _)| self.display_name(id))
+            .collect();
+        names.sort();
+        match names.len() {
+            0 => String::new(),
+            1 => format!("{} is typing…", names[0]),
+            _ => format!("{} are typing…", names.join(", ")),
+        }
+    }
+
+    /// Status line listing the apps currently writing and the files being received
+    fn status(&self) -> String {
+        let mut transfers: Vec<String> = self
+            .transfers
+            .values()
+            .map(|(sender, name, received, total)| {
+                format!(
+                    "receiving {} from {} ({}/{})",
+                    name,
+                    self.display_name(sender),
+                    received,
+                    total
+                )
+            })
+            .collect();
+        transfers.sort();
+        let typing = self.typing_status();
+        if !typing.is_empty() {
+            transfers.insert(0, typing);
+        }
+        transfers.join(" | ")
+    }
+
+    /// Runs a command typed in the input box, except `/quit`
+    fn execute(&mut self, command: Command, server_tx: &mpsc::Sender<ServerEvent>) {
+        match command {
+            Command::Msg(name, text) => {
+                let recipient = self.resolve_id(&name);
+                // Following messages can be sent with Ctrl+p
+                self.private_recipient = Recipient::App(recipient.clone());
+                send_to_server(ServerEvent::UserPrivateMessage(recipient, text), server_tx);
+            }
+            Command::Nick(nickname) => {
+                send_to_server(ServerEvent::SetNickname(nickname), server_tx);
+            }
+            Command::Join(channel) => {
+                if !self.channels.contains(&channel) {
+                    self.channels.push(channel.clone());
+                    send_to_server(ServerEvent::JoinChannel(channel.clone()), server_tx);
+                }
+                self.switch_to(channel.clone());
+                self.push(System(format!("You joined {}", channel)));
+            }
+            Command::Part(channel) => {
+                let channel = channel.unwrap_or_else(|| self.channel.clone());
+                if !self.channels.contains(&channel) {
+                    self.push(Error(format!("You are not in {}", channel)));
+                } else if self.channels.len() == 1 {
+                    self.push(Error("You cannot leave your last channel".to_owned()));
+                } else {
+                    self.channels.retain(|c| *c != channel);
+                    self.messages.remove(&channel);
+                    self.active_channels.remove(&channel);
+                    send_to_server(ServerEvent::PartChannel(channel.clone()), server_tx);
+                    if channel == self.channel {
+                        self.switch_to(self.channels[0].clone());
+                    }
+                    self.push(System(format!("You left {}", channel)));
+                }
+            }
+            Command::Edit(content) => match self.last_own_post() {
+                Some(msg_id) => {
+                    send_to_server(ServerEvent::EditMessage(msg_id, content), server_tx);
+                }
+                None => self.push(Error(
+                    "You have no message to edit in this channel".to_owned(),
+                )),
+            },
+            Command::Delete => match self.last_own_post() {
+                Some(msg_id) => send_to_server(ServerEvent::DeleteMessage(msg_id), server_tx),
+                None => self.push(Error(
+                    "You have no message to delete in this channel".to_owned(),
+                )),
+            },
+            // The selected message, or else the latest one
+            Command::React(emoji) => match self.selected.take().or_else(|| self.last_post()) {
+                Some(msg_id) => send_to_server(ServerEvent::React(msg_id, emoji), server_tx),
+                None => self.push(Error("No message to react to".to_owned())),
+            },
+            Command::SendFile(path, recipient) => {
+                let recipient = recipient.map(|name| self.resolve_id(&name));
+                send_to_server(ServerEvent::SendFile(path, recipient), server_tx);
+            }
+            Command::Clock => send_to_server(ServerEvent::GetClock, server_tx),
+            Command::Snapshot => send_to_server(ServerEvent::GetSnapshot, server_tx),
+            Command::Keys => send_to_server(ServerEvent::GetKeys, server_tx),
+            Command::Who => send_to_server(ServerEvent::GetPeers, server_tx),
+            Command::Help => {
+                for (usage, description) in COMMANDS {
+                    self.push(System(format!("{} -> {}", usage, description)));
+                }
+            }
+            Command::Quit => {}
+        }
+    }
+
+    /// Completes the word before the cursor, or replaces the previous completion
+    /// with the next candidate
+    fn complete(&mut self) {
+        match &mut self.completion {
+            Some(completion) => completion.cycle(),
+            None => {
+                let (start, word) = self.input.word_before_cursor();
+                let names = self
+                    .nicknames
+                    .iter()
+                    .filter(|(id, _)| **id != self.id)
+                    .flat_map(|(id, nickname)| vec![id, nickname]);
+                self.completion = Completion::new(start, word, &self.channels, names);
+            }
+        }
+        if let Some(completion) = &self.completion {
+            self.input
+                .replace_before_cursor(completion.start, completion.current());
+        }
+    }
+
+    /// Resolves a nickname or an id to an app id
+    pub fn resolve_id(&self, name: &str) -> AppId {
+        self.nicknames
+            .iter()
+            .find(|(_, nick)| *nick == name)
+            .map(|(id, _)| id.clone())
+            .unwrap_or_else(|| name.to_owned())
+    }
+}
+
+/// Local time of a timestamp in milliseconds, formatted as by `strftime`
+fn format_time(timestamp: u64, format: &str) -> String {
+    match Local.timestamp_millis_opt(timestamp as i64) {
+        LocalResult::Single(time) => time.format(format).to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Checks a `strftime` format of the time displayed before messages
+pub fn check_time_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("Invalid time format {}", format));
+    }
+    Ok(())
+}
+
 pub fn send_to_server(msg: ServerEvent, server_tx: &mpsc::Sender<ServerEvent>) {
     server_tx
         .send(msg)
@@ -88,47 +749,90 @@ pub fn run(
 
     let events = Events::new(server_rx);
 
-    let mut last_private_id = "no one".to_owned();
+    let mut last_private = Recipient::App("no one".to_owned());
 
     let mut msg_list_size: usize = 0;
+    let mut msg_list_width: usize = 0;
+    // Column and line of the cursor in the input box
+    let mut input_cursor = (0, 0);
 
     loop {
         // Draw UI
         terminal.draw(|mut f| {
+            let input_lines = match app.history.query() {
+                Some(_) => 1,
+                None => app.input.line_count().min(MAX_INPUT_LINES),
+            };
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints(
                     [
                         Constraint::Length(1),
-                        Constraint::Length(3),
+                        Constraint::Length(input_lines as u16 + 2),
                         Constraint::Min(1),
                         Constraint::Length(1),
+                        Constraint::Length(1),
                     ]
                     .as_ref(),
                 )
                 .split(f.size());
 
             msg_list_size = chunks[2].inner(1).height.into();
+            msg_list_width = chunks[2].inner(1).width.into();
 
-            Paragraph::new([Text::raw("NetChat")].iter())
+            Paragraph::new([Text::raw(format!("NetChat - {}", app.nickname))].iter())
                 .alignment(Alignment::Center)
                 .render(&mut f, chunks[0]);
 
-            Paragraph::new([Text::raw(&app.input)].iter())
+            let input_title = match app.selected.and_then(|id| app.post_text(id)) {
+                Some(parent) => format!(
+                    " Input - selected {} (Enter answers, /react reacts, Esc cancels) ",
+                    parent.replace('\n', " ")
+                ),
+                None => " Input ".to_owned(),
+            };
+            let input_title = match app.history.query() {
+                Some(query) => format!(
+                    " Input - reverse search `{}` (^F older, Enter accepts, Esc cancels) ",
+                    query
+                ),
+                None => input_title,
+            };
+            let width: usize = chunks[1].inner(1).width.into();
+            let (input, column, line) = match app.history.query() {
+                Some(_) => {
+                    let found = app.history.found().unwrap_or_default().replace('\n', " ");
+                    let column = found.width().min(width.saturating_sub(1));
+                    (vec![found], column, 0)
+                }
+                None => app.input.view(width, input_lines),
+            };
+            input_cursor = (column, line);
+            Paragraph::new([Text::raw(input.join("\n"))].iter())
                 .style(Style::default().fg(Color::Cyan))
-                .block(Block::default().borders(Borders::ALL).title(" Input "))
+                .block(Block::default().borders(Borders::ALL).title(&input_title))
                 .render(&mut f, chunks[1]);
 
+            let title = format!(" Messages {} ", app.channel_list());
             let messages = app
-                .messages
+                .current_messages()
                 .iter()
                 .rev()
-                .skip(app.first_display_message_id)
-                .map(|m| Text::raw(m.str()));
+                .flat_map(|m| {
+                    let style = app.style(m);
+                    app.wrapped_lines(m, msg_list_width)
+                        .into_iter()
+                        .map(move |line| Text::styled(line, style))
+                })
+                .skip(app.first_display_line);
             List::new(messages)
-                .block(Block::default().borders(Borders::ALL).title(" Messages "))
+                .block(Block::default().borders(Borders::ALL).title(&title))
                 .render(&mut f, chunks[2]);
 
+            Paragraph::new([Text::raw(app.status())].iter())
+                .style(Style::default().modifier(Modifier::ITALIC))
+                .render(&mut f, chunks[3]);
+
             Paragraph::new(
                 [
                     Text::styled("^C", Style::default().modifier(Modifier::REVERSED)),
@@ -137,26 +841,78 @@ pub fn run(
                     Text::raw(" Display clock "),
                     Text::styled("^S", Style::default().modifier(Modifier::REVERSED)),
                     Text::raw(" Snapshot "),
+                    Text::styled("^K", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" Keys "),
                     Text::styled("^P", Style::default().modifier(Modifier::REVERSED)),
                     Text::raw(" Send private message "),
                     Text::styled("^R", Style::default().modifier(Modifier::REVERSED)),
                     Text::raw(" Set pm recipient "),
+                    Text::styled("^N", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" Next channel "),
+                    Text::styled("^T", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" Reply "),
+                    Text::styled("/help", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" Commands "),
                     Text::raw(""),
                 ]
                 .iter(),
             )
-            .render(&mut f, chunks[3]);
+            .render(&mut f, chunks[4]);
         })?;
 
         // Put the cursor back inside the input box
         write!(
             terminal.backend_mut(),
             "{}",
-            Goto(2 + app.input.width() as u16, 3)
+            Goto(2 + input_cursor.0 as u16, 3 + input_cursor.1 as u16)
         )?;
 
+        let event = events.next()?;
+
+        // A key press means the user is in front of the screen
+        // and has seen the private messages received so far
+        if let Event::UserInput(_) = event {
+            if !app.unread.is_empty() {
+                send_to_server(
+                    ServerEvent::MarkRead(app.unread.drain(..).collect()),
+                    &server_tx,
+                );
+            }
+        }
+
+        // Tab cycles through the candidates until another key is pressed
+        if let Event::UserInput(key) = event {
+            if key != Key::Char('\t') {
+                app.completion = None;
+            }
+        }
+
+        // Lines sent are kept to be recalled
+        if let Event::UserInput(Key::Char('\n')) | Event::UserInput(Key::Ctrl('p')) = event {
+            if app.history.query().is_none() {
+                app.history.push(&app.input);
+            }
+        }
+
         // Handle events
-        match events.next()? {
+        match event {
+            // Keys typed during a reverse search of the history
+            Event::UserInput(key) if app.history.query().is_some() => match key {
+                Key::Ctrl('c') => {
+                    break;
+                }
+                Key::Char('\n') | Key::Esc => {
+                    if let Some(line) = app.history.end_search(key == Key::Char('\n')) {
+                        app.input.set(line);
+                    }
+                }
+                Key::Ctrl('f') => app.history.search_older(),
+                Key::Backspace => app.history.edit_query(|query| {
+                    query.pop();
+                }),
+                Key::Char(c) => app.history.edit_query(|query| query.push(c)),
+                _ => {}
+            },
             // Input from the user
             Event::UserInput(input) => match input {
                 Key::Ctrl('c') => {
@@ -168,82 +924,261 @@ pub fn run(
                 Key::Ctrl('s') => {
                     send_to_server(ServerEvent::GetSnapshot, &server_tx);
                 }
+                Key::Ctrl('k') => {
+                    send_to_server(ServerEvent::GetKeys, &server_tx);
+                }
+                Key::Ctrl('n') => {
+                    let index = app
+                        .channels
+                        .iter()
+                        .position(|channel| *channel == app.channel)
+                        .map_or(0, |index| (index + 1) % app.channels.len());
+                    app.switch_to(app.channels[index].clone());
+                }
+                Key::Char('\n') if app.input.starts_with('/') => {
+                    let input = app.input.take();
+                    match commands::parse(&input) {
+                        Ok(Command::Quit) => break,
+                        Ok(command) => app.execute(command, &server_tx),
+                        Err(error) => app.push(Error(error)),
+                    }
+                }
+                Key::Char('\n') if app.selected.is_some() => {
+                    if let Some(parent) = app.selected.take() {
+                        send_to_server(
+                            ServerEvent::UserReply(parent, app.channel.clone(), app.input.take()),
+                            &server_tx,
+                        );
+                    }
+                }
                 Key::Char('\n') => {
                     send_to_server(
-                        ServerEvent::UserPublicMessage(app.input.clone()),
+                        ServerEvent::UserPublicMessage(app.channel.clone(), app.input.take()),
                         &server_tx,
                     );
-                    let message: String = app.input.drain(..).collect();
-                    app.messages.push(User(format!("You: {}", message)));
+                    // The line is displayed once the server has given the message its id
                 }
                 // set the recipient id for private messages
                 Key::Ctrl('r') => {
-                    let private_recipient_id: String = app.input.drain(..).collect();
-                    if private_recipient_id.len() > 0 {
-                        app.private_recipient_id = private_recipient_id;
+                    let private_recipient = app.input.take();
+                    if private_recipient.contains(',') {
+                        // A new group with the given members and ourselves
+                        let mut members: Vec<AppId> = private_recipient
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|name| !name.is_empty())
+                            .map(|name| app.resolve_id(name))
+                            .collect();
+                        members.push(app.id.clone());
+                        members.sort();
+                        members.dedup();
+                        app.private_recipient = Recipient::Group(thread_rng().gen(), members);
+                    } else if !private_recipient.is_empty() {
+                        app.private_recipient = Recipient::App(app.resolve_id(&private_recipient));
                     } else {
-                        app.private_recipient_id = last_private_id.clone();
+                        app.private_recipient = last_private.clone();
                     }
-                    app.messages.push(System(format!(
-                        "Private recipient id set to: {}",
-                        app.private_recipient_id
-                    )));
+                    let line = match &app.private_recipient {
+                        Recipient::App(id) => format!(
+                            "Private recipient set to: {} ({})",
+                            app.display_name(id),
+                            id
+                        ),
+                        Recipient::Group(_, members) => {
+                            format!(
+                                "Private recipient set to the group {}",
+                                app.group_name(members)
+                            )
+                        }
+                    };
+                    app.push(System(line));
                 }
                 Key::Ctrl('p') => {
-                    send_to_server(
-                        ServerEvent::UserPrivateMessage(
-                            app.private_recipient_id.clone(),
-                            app.input.clone(),
-                        ),
-                        &server_tx,
-                    );
-                    let message: String = app.input.drain(..).collect();
-                    app.messages.push(User(format!(
-                        "You to {}: {}",
-                        app.private_recipient_id, message
-                    )));
+                    let message = app.input.take();
+                    let event = match app.private_recipient.clone() {
+                        Recipient::App(id) => ServerEvent::UserPrivateMessage(id, message),
+                        Recipient::Group(group_id, members) => {
+                            ServerEvent::UserGroupMessage(group_id, members, message)
+                        }
+                    };
+                    send_to_server(event, &server_tx);
+                    // The line is displayed once the server has given the message its id
+                }
+                // compose a message of several lines
+                Key::Alt('\r') | Key::Alt('\n') => {
+                    app.input.insert('\n');
+                    app.notify_typing(&server_tx);
+                }
+                Key::Char('\t') => {
+                    app.complete();
                 }
                 Key::Char(c) => {
-                    app.input.push(c);
+                    app.input.insert(c);
+                    app.notify_typing(&server_tx);
                 }
                 Key::Backspace => {
-                    app.input.pop();
+                    app.input.backspace();
+                    app.notify_typing(&server_tx);
+                }
+                Key::Delete => {
+                    app.input.delete();
+                    app.notify_typing(&server_tx);
+                }
+                // erase the word before the cursor
+                Key::Ctrl('w') => {
+                    app.input.kill_word();
+                    app.notify_typing(&server_tx);
+                }
+                // erase everything before the cursor
+                Key::Ctrl('u') => {
+                    app.input.kill_line();
+                    app.notify_typing(&server_tx);
                 }
-                Key::Up => {
-                    app.first_display_message_id = app.first_display_message_id.saturating_sub(1);
+                Key::Left => app.input.left(),
+                Key::Right => app.input.right(),
+                Key::Home | Key::Ctrl('a') => app.input.home(),
+                Key::End | Key::Ctrl('e') => app.input.end(),
+                Key::Alt('b') => app.input.word_left(),
+                Key::Alt('f') => app.input.word_right(),
+                // select a message to answer
+                Key::Ctrl('t') => {
+                    app.move_selection(true, msg_list_width, msg_list_size);
                 }
-                Key::Down => {
-                    app.first_display_message_id = app
-                        .messages
-                        .len()
+                Key::Esc => {
+                    app.selected = None;
+                }
+                Key::Up if app.selected.is_some() => {
+                    app.move_selection(false, msg_list_width, msg_list_size);
+                }
+                Key::Down if app.selected.is_some() => {
+                    app.move_selection(true, msg_list_width, msg_list_size);
+                }
+                // move between the lines of the input, past them recall the lines sent
+                Key::Up if !app.input.up() => {
+                    if let Some(line) = app.history.previous(&app.input) {
+                        app.input.set(line.to_owned());
+                    }
+                }
+                Key::Down if !app.input.down() => {
+                    if let Some(line) = app.history.next() {
+                        app.input.set(line.to_owned());
+                    }
+                }
+                Key::Ctrl('f') => {
+                    app.history.start_search(&app.input);
+                }
+                Key::PageUp => {
+                    app.first_display_line = app.first_display_line.saturating_sub(msg_list_size);
+                }
+                Key::PageDown => {
+                    let lines: usize = app
+                        .current_messages()
+                        .iter()
+                        .map(|m| app.wrapped_lines(m, msg_list_width).len())
+                        .sum();
+                    app.first_display_line = lines
                         .saturating_sub(msg_list_size)
-                        .min(app.first_display_message_id + 1);
+                        .min(app.first_display_line + msg_list_size);
                 }
                 _ => {}
             },
             // Input from a distant app
-            Event::DistantMessage(msg) => match &msg.header {
-                Public(content) => {
-                    app.messages
-                        .push(User(format!("{}: {}", msg.sender_id, content)));
+            Event::DistantMessage(msg) => {
+                app.typing.remove(&msg.sender_id);
+                app.show(&msg, "");
+                if let Private(_, _) = msg.header {
+                    app.unread.push((msg.sender_id.clone(), msg.id));
+                    last_private = Recipient::App(msg.sender_id.clone());
                 }
-                Private(_, content) => {
-                    app.messages
-                        .push(User(format!("{} to You: {}", msg.sender_id, content)));
-                    last_private_id = msg.sender_id;
+                // Replies go to the same group
+                if let Group(group_id, members, _) = &msg.header {
+                    last_private = Recipient::Group(*group_id, members.clone());
                 }
-                _ => {}
+            }
+            Event::Sent(msg) => match msg.header {
+                Private(_, _) | Group(_, _, _) => {
+                    if let Some(line) = app.message_line(&msg) {
+                        app.push(Outgoing(line, msg.id, Receipt::Sent));
+                    }
+                }
+                _ => app.show(&msg, ""),
             },
+            Event::Receipt(msg_id, receipt) => {
+                app.set_receipt(msg_id, receipt);
+            }
+            Event::Backlog(backlog) => {
+                for msg in &backlog {
+                    app.show(msg, "[backlog] ");
+                }
+            }
+            Event::History(history) => {
+                if !history.is_empty() {
+                    for msg in &history {
+                        app.show(msg, "");
+                    }
+                    app.push(System("--- end of the saved history ---".to_owned()));
+                }
+            }
+            Event::DisplayKeys(fingerprints) => {
+                for (id, fingerprint) in fingerprints {
+                    app.push(System(format!(
+                        "Key of {} ({}): {}",
+                        app.display_name(&id),
+                        id,
+                        fingerprint
+                    )));
+                }
+            }
+            Event::DisplayPeers(peers) => {
+                let names: Vec<String> = peers
+                    .iter()
+                    .map(|(id, nickname)| format!("{} ({})", nickname, id))
+                    .collect();
+                app.push(System(if names.is_empty() {
+                    "No one else is connected".to_owned()
+                } else {
+                    format!("Connected: {}", names.join(", "))
+                }));
+            }
             Event::DisplayClock(clock) => {
                 for (id, date) in clock.0 {
-                    app.messages
-                        .push(System(format!("App {} date: {}", id, date)));
+                    app.push(System(format!("App {} date: {}", id, date)));
                 }
             }
+            Event::PeerNickname(id, nickname) => {
+                if id == app.id {
+                    app.push(System(format!("You are now known as {}", nickname)));
+                    app.nickname = nickname.clone();
+                }
+                app.nicknames.insert(id, nickname);
+            }
+            Event::Renamed(id, nickname) => {
+                app.id = id;
+                app.nickname = nickname;
+            }
+            Event::Fatal(error) => {
+                return Err(error.into());
+            }
             Event::ServerMessage(string) => {
-                app.messages.push(System(format!("Server: {}", string)));
+                app.push(System(format!("Server: {}", string)));
+            }
+            Event::ServerError(string) => {
+                app.push(Error(format!("Server: {}", string)));
+            }
+            Event::FileProgress(transfer_id, sender, name, received, total) => {
+                app.transfers
+                    .insert(transfer_id, (sender, name, received, total));
+            }
+            Event::FileDone(transfer_id) => {
+                app.transfers.remove(&transfer_id);
+            }
+            Event::Typing(id) => {
+                app.typing.insert(id, Instant::now());
+            }
+            Event::Tick => {
+                app.typing
+                    .retain(|_, since| since.elapsed() < TYPING_DISPLAY);
             }
-            Event::Tick => {}
         }
     }
 
@@ -251,3 +1186,40 @@ pub fn run(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Clock;
+
+    #[test]
+    fn clock_skew() {
+        let mut app = App::default();
+        let msg = |id: MsgId, sender: &str, timestamp: u64, reply_to: Option<MsgId>| {
+            // The sender dates follow the ids
+            let mut msg = Msg::new(
+                id,
+                sender.to_owned(),
+                Public(DEFAULT_CHANNEL.to_owned(), "I like trains !".to_owned()),
+                Clock(vec![(sender.to_owned(), id as Date)].into_iter().collect()),
+            );
+            msg.timestamp = Some(timestamp);
+            msg.reply_to = reply_to;
+            msg
+        };
+
+        assert!(!app.out_of_order(&msg(1, "alice", 2_000, None)));
+        // Concurrent messages of other apps may have any timestamp
+        assert!(!app.out_of_order(&msg(2, "bob", 1_000, None)));
+        assert!(app.out_of_order(&msg(3, "alice", 1_500, None)));
+        assert!(app.out_of_order(&msg(4, "bob", 1_800, Some(1))));
+        // Earlier messages replayed after later ones are in order
+        assert!(!app.out_of_order(&msg(0, "alice", 1_000, None)));
+
+        app.time_format = "%Y".to_owned();
+        let line = app.message_line(&msg(5, "bob", 1_561_939_200_000, None));
+        assert_eq!(line.as_deref(), Some("[2019] bob: I like trains !"));
+        assert!(check_time_format("%H:%M").is_ok());
+        assert!(check_time_format("%H:%Q").is_err());
+    }
+}
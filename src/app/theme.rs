@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use tui::style::{Color, Modifier, Style};
+
+/// Styles of the kinds of messages, and colours given to their senders
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub system: Style,
+    pub error: Style,
+    /// Messages of the user
+    pub own: Style,
+    /// Public messages of the other users, displayed in the colour of their sender
+    pub public: Style,
+    /// Private and group messages, displayed in the colour of their sender
+    pub private: Style,
+    /// Quote of the message answered by the following one
+    pub quote: Style,
+    /// Colours the other users are given, according to their id
+    senders: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            system: Style::default().fg(Color::DarkGray),
+            error: Style::default()
+                .fg(Color::LightRed)
+                .modifier(Modifier::BOLD),
+            own: Style::default().fg(Color::Cyan),
+            public: Style::default(),
+            private: Style::default().modifier(Modifier::ITALIC),
+            quote: Style::default().fg(Color::Gray),
+            senders: vec![
+                Color::Green,
+                Color::Yellow,
+                Color::Blue,
+                Color::Magenta,
+                Color::LightGreen,
+                Color::LightYellow,
+                Color::LightBlue,
+                Color::LightMagenta,
+            ],
+        }
+    }
+}
+
+/// Content of a theme file, the styles and colours left out keep their default
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    system: Option<StyleFile>,
+    error: Option<StyleFile>,
+    own: Option<StyleFile>,
+    public: Option<StyleFile>,
+    private: Option<StyleFile>,
+    quote: Option<StyleFile>,
+    senders: Option<Vec<String>>,
+}
+
+/// Style of a kind of messages, colours are given as for `color`
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StyleFile {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underlined: bool,
+}
+
+impl StyleFile {
+    fn style(&self) -> Result<Style, String> {
+        let mut style = Style::default();
+        if let Some(fg) = &self.fg {
+            style = style.fg(color(fg)?);
+        }
+        if let Some(bg) = &self.bg {
+            style = style.bg(color(bg)?);
+        }
+        let modifiers = [
+            (self.bold, Modifier::BOLD),
+            (self.dim, Modifier::DIM),
+            (self.italic, Modifier::ITALIC),
+            (self.underlined, Modifier::UNDERLINED),
+        ];
+        let modifier = modifiers
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(Modifier::empty(), |modifier, (_, flag)| modifier | *flag);
+        Ok(style.modifier(modifier))
+    }
+}
+
+impl Theme {
+    /// Reads a JSON theme file, fails with a message for the user if it is invalid
+    pub fn load(path: &Path) -> Result<Theme, String> {
+        let invalid = |e: String| format!("Invalid theme {}: {}", path.display(), e);
+        let content = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let file: ThemeFile = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+
+        let mut theme = Theme::default();
+        let styles = [
+            (&file.system, &mut theme.system),
+            (&file.error, &mut theme.error),
+            (&file.own, &mut theme.own),
+            (&file.public, &mut theme.public),
+            (&file.private, &mut theme.private),
+            (&file.quote, &mut theme.quote),
+        ];
+        for (style, target) in styles {
+            if let Some(style) = style {
+                *target = style.style().map_err(invalid)?;
+            }
+        }
+        if let Some(senders) = &file.senders {
+            if senders.is_empty() {
+                return Err(invalid("no sender colour".to_owned()));
+            }
+            theme.senders = senders
+                .iter()
+                .map(|name| color(name))
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?;
+        }
+        Ok(theme)
+    }
+
+    /// Colour of an app, always the same for a given id
+    pub fn sender_color(&self, id: &str) -> Color {
+        // The hasher of the standard library may change between releases
+        let hash = id.bytes().fold(0u32, |hash, byte| {
+            hash.wrapping_mul(31).wrapping_add(byte.into())
+        });
+        self.senders[hash as usize % self.senders.len()]
+    }
+}
+
+/// Colour from its name, `#rrggbb` or its index among the 256 colours of the terminal
+fn color(name: &str) -> Result<Color, String> {
+    let color = match name.to_lowercase().replace('-', "_").as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "dark_gray" => Color::DarkGray,
+        "light_red" => Color::LightRed,
+        "light_green" => Color::LightGreen,
+        "light_yellow" => Color::LightYellow,
+        "light_blue" => Color::LightBlue,
+        "light_magenta" => Color::LightMagenta,
+        "light_cyan" => Color::LightCyan,
+        "white" => Color::White,
+        rgb if rgb.starts_with('#') && rgb.len() == 7 && rgb.is_ascii() => {
+            let component = |i| u8::from_str_radix(&rgb[i..i + 2], 16);
+            match (component(1), component(3), component(5)) {
+                (Ok(r), Ok(g), Ok(b)) => Color::Rgb(r, g, b),
+                _ => return Err(format!("unknown colour {}", name)),
+            }
+        }
+        index => match index.parse() {
+            Ok(index) => Color::Indexed(index),
+            Err(_) => return Err(format!("unknown colour {}", name)),
+        },
+    };
+    Ok(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_file() {
+        let file = std::env::temp_dir().join(format!("netchat-theme-{}", std::process::id()));
+        fs::write(
+            &file,
+            r##"{"own": {"fg": "light-cyan", "bold": true}, "senders": ["red", "#ff8800", "208"]}"##,
+        )
+        .unwrap();
+        let theme = Theme::load(&file).unwrap();
+        assert_eq!(
+            theme.own,
+            Style::default()
+                .fg(Color::LightCyan)
+                .modifier(Modifier::BOLD)
+        );
+        assert_eq!(theme.private, Theme::default().private);
+        assert_eq!(
+            theme.senders,
+            vec![Color::Red, Color::Rgb(255, 136, 0), Color::Indexed(208)]
+        );
+        // The same on every run, for every app
+        assert_eq!(theme.sender_color("alice"), Color::Red);
+
+        fs::write(&file, r#"{"error": {"fg": "mauve"}}"#).unwrap();
+        assert!(Theme::load(&file)
+            .unwrap_err()
+            .ends_with("unknown colour mauve"));
+        fs::write(&file, r##"{"error": {"fg": "#a€bc"}}"##).unwrap();
+        assert!(Theme::load(&file)
+            .unwrap_err()
+            .ends_with("unknown colour #a€bc"));
+        fs::remove_file(file).unwrap();
+    }
+}
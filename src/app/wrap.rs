@@ -0,0 +1,76 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Indentation of the lines following the first one of a message
+const INDENT: &str = "  ";
+
+/// Splits `text` in lines of at most `width` columns, breaking at spaces when possible
+/// and at newlines. The lines following the first one are indented.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    // Leaves room for at least one char after the indentation
+    let width = width.max(INDENT.width() + 2);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut used = 0;
+    // Whether nothing but the indentation was written on the line
+    let mut empty = true;
+
+    for (i, paragraph) in text.split('\n').enumerate() {
+        if i > 0 {
+            lines.push(line);
+            line = INDENT.to_owned();
+            used = INDENT.width();
+            empty = true;
+        }
+        for word in paragraph.split(' ') {
+            if !empty {
+                // Words too wide for any line are broken where they start
+                let fits_a_line = word.width() <= width - INDENT.width();
+                if used + 1 + word.width() > width && (fits_a_line || used + 1 >= width) {
+                    lines.push(line);
+                    line = INDENT.to_owned();
+                    used = INDENT.width();
+                    empty = true;
+                } else {
+                    line.push(' ');
+                    used += 1;
+                }
+            }
+            for c in word.chars() {
+                let c_width = c.width().unwrap_or(0);
+                if used + c_width > width && !empty {
+                    lines.push(line);
+                    line = INDENT.to_owned();
+                    used = INDENT.width();
+                }
+                line.push(c);
+                used += c_width;
+                empty = false;
+            }
+            empty = false;
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_wrapping() {
+        assert_eq!(wrap("bob: I like trains", 30), vec!["bob: I like trains"]);
+        assert_eq!(
+            wrap("bob: I like trains", 12),
+            vec!["bob: I like", "  trains"]
+        );
+        assert_eq!(wrap("bob: choo\nchoo", 12), vec!["bob: choo", "  choo"]);
+        assert_eq!(
+            wrap("bob: 🚂🚂🚂🚂🚂🚂", 8),
+            vec!["bob: 🚂", "  🚂🚂🚂", "  🚂🚂"]
+        );
+        for line in wrap("alice: électrique 🚂 trains everywhere", 9) {
+            assert!(line.width() <= 9, "{} is too wide", line);
+        }
+    }
+}
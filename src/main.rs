@@ -1,10 +1,9 @@
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 
 use gag::Redirect;
-use log;
 
 use structopt::StructOpt;
 
@@ -18,33 +17,125 @@ use app::App;
 #[structopt(name = "netchat")]
 /// A fully decentralized (thus inefficient) chat written in rust
 ///
-/// Enter  -> sends the content of the input field to everyone
+/// Enter  -> sends the content of the input field to the current channel
+///
+/// Alt+Enter -> starts a new line in the input field
 ///
 /// Ctrl+c -> exit
 ///
+/// /help  -> lists the commands, lines starting with / are commands and are not sent
+///
+/// /msg   -> sends the rest of the input field after the nickname or id following it
+/// to that user privately
+///
+/// /who   -> lists the connected users
+///
+/// /clock, /snapshot, /keys, /quit -> same as Ctrl+h, Ctrl+s, Ctrl+k and Ctrl+c
+///
 /// Ctrl+s -> get a snapshot containing every messages sent by every site
 ///
-/// Ctrl+r -> set the private message recipient id to the content of the input field or,
-/// if left empty, to the id which sent the last private message
+/// Ctrl+k -> display the fingerprints of the keys used to encrypt private messages
+///
+/// /nick  -> changes your nickname to the rest of the input field
+///
+/// /join  -> joins the channel named in the rest of the input field and displays it
+///
+/// /part  -> leaves the channel named in the rest of the input field, or the current one
+///
+/// Ctrl+n -> displays the next joined channel
+///
+/// /edit  -> replaces your last message in the current channel by the rest of the input field
+///
+/// /delete -> deletes your last message in the current channel
+///
+/// Ctrl+t -> selects a message to answer, Up and Down move the selection, Enter sends
+/// the answer and Esc cancels
+///
+/// /react -> reacts to the selected message, or the latest one, with the rest of the input field
+///
+/// /send-file -> sends the file at the path given in the rest of the input field,
+/// to the nickname or id following it if any, to everyone otherwise
+///
+/// Ctrl+r -> set the private message recipient to the nickname or id in the input field,
+/// to a new group if several comma separated names are given or, if left empty,
+/// to the id or group which sent the last private message
 ///
 /// Ctrl+p -> sends the content of the input field to the current private recipient
 ///
-/// Up     -> scroll messages up
+/// Left, Right, Home, End (or Ctrl+a, Ctrl+e), Alt+b, Alt+f -> move the cursor in the input field
+/// by char, to its ends or by word
+///
+/// Delete, Ctrl+w, Ctrl+u -> erase the char under the cursor, the word before it
+/// or everything before it
+///
+/// Tab    -> completes the word before the cursor: commands, channels after a #,
+/// nicknames and ids otherwise, again for the next candidate
+///
+/// Up, Down -> move between the lines of the input field, then recall the previous
+/// or next line sent
 ///
-/// Down   -> scroll messages down
+/// Ctrl+f -> searches the lines sent for the text typed next, again for older ones,
+/// Enter puts the line found in the input field and Esc cancels
+///
+/// PageUp -> scroll messages up
+///
+/// PageDown -> scroll messages down
 pub struct Opt {
-    /// Input file
-    #[structopt(short = "i", long = "input", parse(from_os_str))]
-    input: PathBuf,
+    /// Input files, with several links the n-th input must come from the neighbour the n-th output goes to
+    #[structopt(
+        short = "i",
+        long = "input",
+        parse(from_os_str),
+        raw(required = "true", number_of_values = "1")
+    )]
+    input: Vec<PathBuf>,
 
-    /// Output file
-    #[structopt(short = "o", long = "output", parse(from_os_str))]
-    output: PathBuf,
+    /// Output files, one per neighbour
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        raw(required = "true", number_of_values = "1")
+    )]
+    output: Vec<PathBuf>,
 
-    //Application Identifier
+    /// Nickname displayed to the other users, defaults to the application id
     #[structopt(short = "n", long = "name")]
+    name: Option<String>,
+
+    /// Application identifier used to route messages, randomly generated if not set
+    #[structopt(long = "id")]
     id: Option<String>,
 
+    /// Add a random suffix to the id if it is already used, instead of exiting
+    #[structopt(long = "rename-on-conflict")]
+    rename_on_conflict: bool,
+
+    /// Secret shared by the members of the network, frames from apps without it are rejected
+    #[structopt(long = "secret")]
+    secret: Option<String>,
+
+    /// Directory where the identity, clock and messages are saved between runs
+    #[structopt(short = "d", long = "data-dir", parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+
+    /// `strftime` format of the local time displayed before messages, empty to hide it
+    #[structopt(long = "time-format", default_value = "%H:%M")]
+    time_format: String,
+
+    /// JSON file of the styles of the messages and colours of their senders
+    #[structopt(long = "theme", parse(from_os_str))]
+    theme: Option<PathBuf>,
+
+    /// Directory where received files are saved, defaults to `downloads` in the data directory,
+    /// or in the current one
+    #[structopt(long = "downloads", parse(from_os_str))]
+    downloads: Option<PathBuf>,
+
+    /// File containing the secret shared by the members of the network
+    #[structopt(long = "secret-file", parse(from_os_str), conflicts_with = "secret")]
+    secret_file: Option<PathBuf>,
+
     //Application Identifier
     #[structopt(short = "l", long = "logfile")]
     logfile: Option<PathBuf>,
@@ -53,6 +144,27 @@ pub struct Opt {
 fn main() {
     let opt = Opt::from_args();
 
+    if opt.input.len() != opt.output.len() {
+        structopt::clap::Error::with_description(
+            "there must be as many inputs as outputs",
+            structopt::clap::ErrorKind::WrongNumberOfValues,
+        )
+        .exit();
+    }
+
+    if let Err(e) = app::check_time_format(&opt.time_format) {
+        structopt::clap::Error::with_description(&e, structopt::clap::ErrorKind::InvalidValue)
+            .exit();
+    }
+
+    let theme = match &opt.theme {
+        Some(path) => app::theme::Theme::load(path).unwrap_or_else(|e| {
+            structopt::clap::Error::with_description(&e, structopt::clap::ErrorKind::InvalidValue)
+                .exit()
+        }),
+        None => app::theme::Theme::default(),
+    };
+
     // Open a log file
     let logfile = opt.logfile.clone().unwrap_or("/tmp/netchat.log".into());
     let log = OpenOptions::new()
@@ -73,16 +185,62 @@ fn main() {
     // Create default app state
     let mut app = App::default();
 
+    let store = opt.data_dir.as_ref().map(|data_dir| {
+        server::store::Store::open(data_dir).expect("Could not open the data directory")
+    });
+    if let Some(data_dir) = &opt.data_dir {
+        app.history = app::history::History::open(data_dir.join("input_history.jsonl"));
+    }
+    let identity = store.as_ref().and_then(|store| store.identity());
+    let restored = identity.is_some();
+    if let Some(identity) = identity {
+        app.id = identity.app_id;
+        app.nickname = identity.nickname;
+    }
+
     if let Some(id) = opt.id.to_owned() {
+        // The id is only the default nickname, a restored one is kept
+        if !restored {
+            app.nickname = id.clone();
+        }
         app.id = id;
     }
+    if let Some(name) = opt.name.to_owned() {
+        app.nickname = name;
+    }
+    app.time_format = opt.time_format.clone();
+    app.theme = theme;
 
-    app.messages.push(app::Message::System(format!(
-        "input : {:?}, output : {:?}, id : {}",
-        opt.input, opt.output, app.id
+    app.push(app::Message::System(format!(
+        "input : {:?}, output : {:?}, id : {}, nickname : {}",
+        opt.input, opt.output, app.id, app.nickname
     )));
 
-    let server = Server::new(app.id.to_owned());
+    let secret = match (&opt.secret, &opt.secret_file) {
+        (Some(secret), _) => Some(secret.as_bytes().to_vec()),
+        (None, Some(path)) => Some(
+            fs::read_to_string(path)
+                .expect("Could not read secret file")
+                .trim_end()
+                .as_bytes()
+                .to_vec(),
+        ),
+        (None, None) => None,
+    };
+
+    let config = server::Config {
+        rename_on_id_conflict: opt.rename_on_conflict,
+        secret,
+        downloads_dir: opt.downloads.clone().unwrap_or_else(|| {
+            opt.data_dir
+                .as_ref()
+                .map_or_else(|| "downloads".into(), |data_dir| data_dir.join("downloads"))
+        }),
+    };
+    let mut server = Server::new(app.id.to_owned(), app.nickname.to_owned(), config);
+    if let Some(store) = store {
+        server.restore(store, &app_tx);
+    }
 
     let server_handle = thread::spawn(move || {
         if let Err(e) = server::run(server, app_rx, app_tx, opt.input, opt.output) {
@@ -92,6 +250,8 @@ fn main() {
 
     if let Err(e) = app::run(app, server_rx, server_tx) {
         log::error!("{}", e);
+        // The terminal has been restored, let the user know why we exited
+        println!("{}", e);
     };
 
     server_handle
@@ -0,0 +1,221 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Size of the ChaCha20Poly1305 nonce prepended to each ciphertext
+const NONCE_SIZE: usize = 12;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key pairs of the local app: X25519 for end-to-end encryption of private
+/// messages and Ed25519 for signing every message
+pub struct Keys {
+    secret: StaticSecret,
+    pub public: PublicKey,
+    signing: SigningKey,
+    pub verifying: VerifyingKey,
+}
+
+impl Keys {
+    pub fn generate() -> Self {
+        let secret = StaticSecret::random();
+        let public = PublicKey::from(&secret);
+        let signing = SigningKey::generate(&mut OsRng);
+        let verifying = signing.verifying_key();
+        Keys {
+            secret,
+            public,
+            signing,
+            verifying,
+        }
+    }
+
+    /// Hex encoded secret halves of the key pairs, to be saved to disk
+    pub fn to_secret_hex(&self) -> String {
+        let mut bytes = self.secret.to_bytes().to_vec();
+        bytes.extend_from_slice(&self.signing.to_bytes());
+        hex::encode(bytes)
+    }
+
+    /// Restores key pairs saved with `to_secret_hex`
+    pub fn from_secret_hex(secret_hex: &str) -> Option<Self> {
+        let mut bytes = [0; 64];
+        hex::decode_to_slice(secret_hex, &mut bytes).ok()?;
+        let mut secret_bytes = [0; 32];
+        secret_bytes.copy_from_slice(&bytes[..32]);
+        let mut signing_bytes = [0; 32];
+        signing_bytes.copy_from_slice(&bytes[32..]);
+
+        let secret = StaticSecret::from(secret_bytes);
+        let public = PublicKey::from(&secret);
+        let signing = SigningKey::from_bytes(&signing_bytes);
+        let verifying = signing.verifying_key();
+        Some(Keys {
+            secret,
+            public,
+            signing,
+            verifying,
+        })
+    }
+
+    /// Hex encoded signature of `bytes`
+    pub fn sign(&self, bytes: &[u8]) -> String {
+        hex::encode(self.signing.sign(bytes).to_bytes())
+    }
+
+    /// Symmetric key shared with the owner of `public`, both ends of the
+    /// conversation derive the same one
+    fn shared_key(&self, public: &PublicKey) -> Key {
+        let shared_secret = self.secret.diffie_hellman(public);
+        let mut hasher = Sha256::new();
+        hasher.update(b"netchat private message");
+        hasher.update(shared_secret.as_bytes());
+        hasher.finalize()
+    }
+
+    /// Encrypts `plaintext` for the owner of `recipient`,
+    /// the result is hex encoded to be sent as a message content
+    pub fn encrypt(&self, recipient: &PublicKey, plaintext: &str) -> String {
+        let cipher = ChaCha20Poly1305::new(&self.shared_key(recipient));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("Failed to encrypt private message");
+
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+        hex::encode(payload)
+    }
+
+    /// Decrypts a content produced by `encrypt` from the owner of `sender`,
+    /// fails if the content was not encrypted for us or has been tampered with
+    pub fn decrypt(&self, sender: &PublicKey, content: &str) -> Option<String> {
+        let payload = hex::decode(content).ok()?;
+        if payload.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_SIZE);
+        let cipher = ChaCha20Poly1305::new(&self.shared_key(sender));
+        let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+pub fn encode_public_key(public: &PublicKey) -> String {
+    hex::encode(public.as_bytes())
+}
+
+pub fn decode_public_key(public: &str) -> Option<PublicKey> {
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(public, &mut bytes).ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+pub fn encode_verifying_key(verifying: &VerifyingKey) -> String {
+    hex::encode(verifying.as_bytes())
+}
+
+pub fn decode_verifying_key(verifying: &str) -> Option<VerifyingKey> {
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(verifying, &mut bytes).ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+/// Checks that `signature` is a signature of `bytes` by the owner of `verifying`
+pub fn verify(verifying: &VerifyingKey, bytes: &[u8], signature: &str) -> bool {
+    let mut signature_bytes = [0; 64];
+    if hex::decode_to_slice(signature, &mut signature_bytes).is_err() {
+        return false;
+    }
+    verifying
+        .verify(bytes, &Signature::from_bytes(&signature_bytes))
+        .is_ok()
+}
+
+/// Short human readable digest of a key, to be compared out of band
+pub fn fingerprint(key: &[u8]) -> String {
+    let digest = Sha256::digest(key);
+    digest[..8]
+        .chunks(2)
+        .map(hex::encode)
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn frame_mac(secret: &[u8]) -> HmacSha256 {
+    <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any size")
+}
+
+/// Prefixes a frame with its HMAC under the network shared secret
+pub fn seal_frame(secret: &[u8], frame: &str) -> String {
+    let mut mac = frame_mac(secret);
+    mac.update(frame.as_bytes());
+    format!("{} {}", hex::encode(mac.finalize().into_bytes()), frame)
+}
+
+/// Checks and strips the HMAC prefix of a frame,
+/// fails if it is missing or does not match the shared secret
+pub fn open_frame<'a>(secret: &[u8], line: &'a str) -> Option<&'a str> {
+    let mut parts = line.splitn(2, ' ');
+    let tag = hex::decode(parts.next()?).ok()?;
+    let frame = parts.next()?;
+    let mut mac = frame_mac(secret);
+    mac.update(frame.as_bytes());
+    mac.verify_slice(&tag).ok()?;
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_message_encryption() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let eve = Keys::generate();
+
+        let content = alice.encrypt(&bob.public, "I like trains !");
+        assert_eq!(
+            bob.decrypt(&alice.public, &content),
+            Some("I like trains !".to_owned())
+        );
+        assert_eq!(eve.decrypt(&alice.public, &content), None);
+
+        let public = encode_public_key(&alice.public);
+        assert_eq!(decode_public_key(&public), Some(alice.public));
+
+        let restored = Keys::from_secret_hex(&bob.to_secret_hex()).expect("invalid secret keys");
+        assert_eq!(
+            restored.decrypt(&alice.public, &content),
+            Some("I like trains !".to_owned())
+        );
+    }
+
+    #[test]
+    fn message_signature() {
+        let alice = Keys::generate();
+        let eve = Keys::generate();
+
+        let signature = alice.sign(b"I like trains !");
+        assert!(verify(&alice.verifying, b"I like trains !", &signature));
+        assert!(!verify(&alice.verifying, b"I hate trains !", &signature));
+        assert!(!verify(&eve.verifying, b"I like trains !", &signature));
+        assert!(!verify(&alice.verifying, b"I like trains !", ""));
+    }
+
+    #[test]
+    fn frame_authentication() {
+        let frame = seal_frame(b"secret", "{\"I like\": \"trains !\"}");
+        assert_eq!(
+            open_frame(b"secret", &frame),
+            Some("{\"I like\": \"trains !\"}")
+        );
+        assert_eq!(open_frame(b"other secret", &frame), None);
+        assert_eq!(open_frame(b"secret", "{\"I like\": \"trains !\"}"), None);
+        assert_eq!(open_frame(b"secret", &frame.replace("like", "hate")), None);
+    }
+}
@@ -1,27 +1,67 @@
-use std::fs::File;
+use super::crypto;
+use super::messages::{Channel, GroupId, MsgId};
 use crate::app::AppId;
+use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 
 pub enum Event {
-    /// User public message
-    UserPublicMessage(String),
+    /// User public message, along with its channel
+    UserPublicMessage(Channel, String),
+    /// User answer to a public message, along with the id of that message
+    UserReply(MsgId, Channel, String),
     /// User private message
     UserPrivateMessage(AppId, String),
-    /// Message from another app (write in a file)
-    DistantInput(String),
+    /// New content for a public message of the user
+    EditMessage(MsgId, String),
+    /// Deletion of a public message of the user
+    DeleteMessage(MsgId),
+    /// Reaction of the user to a public message
+    React(MsgId, String),
+    /// File to send, to a single app or to everyone
+    SendFile(PathBuf, Option<AppId>),
+    /// User message to a group, along with the group members
+    UserGroupMessage(GroupId, Vec<AppId>, String),
+    /// Message from another app (write in a file), along with the index of
+    /// the input file it was read from
+    DistantInput(usize, String),
+    /// Frame read from an input file without a valid HMAC, along with the index of the file
+    RejectedFrame(usize),
+    /// The user is writing a message
+    UserTyping,
+    /// Private messages seen by the user, with their senders
+    MarkRead(Vec<(AppId, MsgId)>),
+    /// The user joined a channel
+    JoinChannel(Channel),
+    /// The user left a channel
+    PartChannel(Channel),
+    /// Nickname change requested by the user
+    SetNickname(String),
     /// Shutdown the server
     Shutdown,
     /// Clock request from the user
     GetClock,
+    /// Public key fingerprints request from the user
+    GetKeys,
+    /// Connected peers request from the user
+    GetPeers,
     /// Snapshot request from the user
     GetSnapshot,
-    /// Timer used for snapshot building. When finished, 
+    /// Timer used for snapshot building. When finished,
     /// the server stops waiting for Snapshots from other apps,
     /// and writes the snapshot to file
     SnapshotTimeout,
+    /// Timer used for the app id uniqueness challenge. When finished,
+    /// the id is considered free and the connection is announced
+    IdProbeTimeout(u64),
+    /// Timer used for history catch-up. When finished, the server stops
+    /// waiting for missed messages and delivers them to the app
+    SyncTimeout,
+    /// Timer used for file transfers. When finished, a download which got
+    /// no new piece in the meantime is dropped, along with the transfer id
+    DownloadTimeout(u64),
 }
 
 /// A small event handler that wrap termion input and tick events. Each event
@@ -29,12 +69,13 @@ pub enum Event {
 pub struct Events {
     rx: mpsc::Receiver<Event>,
     _app_handle: thread::JoinHandle<()>,
-    _input_file_handle: thread::JoinHandle<()>,
+    _input_file_handles: Vec<thread::JoinHandle<()>>,
 }
 
 impl Events {
     pub fn new(
-        input_file_path: PathBuf,
+        input_file_paths: Vec<PathBuf>,
+        secret: Option<Vec<u8>>,
         app_rx: mpsc::Receiver<Event>,
         server_rx: mpsc::Receiver<Event>,
     ) -> Events {
@@ -54,20 +95,32 @@ impl Events {
             })
         };
 
-        // listen to the server for distant events
-        let _input_file_handle = {
-            let tx = tx.clone();
-            thread::spawn(move || loop {
-                let input_file = File::open(&input_file_path).expect("Could not open input file");
-                let reader = BufReader::new(input_file);
-                reader.lines().for_each(|line| {
-                    tx.send(Event::DistantInput(
-                        line.expect("Could not read from input file"),
-                    ))
-                    .unwrap();
+        // listen to the input files for distant events
+        let _input_file_handles = input_file_paths
+            .into_iter()
+            .enumerate()
+            .map(|(link, input_file_path)| {
+                let tx = tx.clone();
+                let secret = secret.clone();
+                thread::spawn(move || loop {
+                    let input_file =
+                        File::open(&input_file_path).expect("Could not open input file");
+                    let reader = BufReader::new(input_file);
+                    reader.lines().for_each(|line| {
+                        let line = line.expect("Could not read from input file");
+                        // Only authenticated frames reach the server when a secret is set
+                        let event = match &secret {
+                            Some(secret) => match crypto::open_frame(secret, &line) {
+                                Some(frame) => Event::DistantInput(link, frame.to_owned()),
+                                None => Event::RejectedFrame(link),
+                            },
+                            None => Event::DistantInput(link, line),
+                        };
+                        tx.send(event).unwrap();
+                    })
                 })
             })
-        };
+            .collect();
 
         // listen to server events to allow to speak to itself asynchronously
         let _server_handle = {
@@ -83,7 +136,7 @@ impl Events {
         Events {
             rx,
             _app_handle,
-            _input_file_handle,
+            _input_file_handles,
         }
     }
 
@@ -0,0 +1,49 @@
+use crate::app::AppId;
+use std::time::Duration;
+
+use super::messages::{Msg, MsgId};
+use super::queue::ExpiringQueue;
+
+/// Maximum number of private messages held for offline apps
+const CAPACITY: usize = 256;
+/// Time after which an undelivered private message is dropped
+const TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Private messages addressed to apps which were offline when they were sent,
+/// held until their recipient comes back or they expire
+#[derive(Debug)]
+pub struct Mailbox {
+    pending: ExpiringQueue<(AppId, Msg)>,
+}
+
+impl Default for Mailbox {
+    fn default() -> Mailbox {
+        Mailbox {
+            pending: ExpiringQueue::new(CAPACITY, TTL),
+        }
+    }
+}
+
+impl Mailbox {
+    /// Holds `msg` for `recipient`, the oldest message is dropped when full
+    pub fn hold(&mut self, recipient: &AppId, msg: Msg) {
+        if self.pending.iter().any(|(_, held)| held.id == msg.id) {
+            return;
+        }
+        self.pending.push((recipient.to_owned(), msg));
+    }
+
+    /// Messages still waiting for `recipient`, expired ones are dropped
+    pub fn pending_for(&mut self, recipient: &AppId) -> Vec<Msg> {
+        self.pending
+            .iter()
+            .filter(|(app_id, _)| app_id == recipient)
+            .map(|(_, msg)| msg.clone())
+            .collect()
+    }
+
+    /// Forgets a message once it has been delivered, returns whether it was held
+    pub fn remove(&mut self, msg_id: MsgId) -> bool {
+        !self.pending.take(|(_, msg)| msg.id == msg_id).is_empty()
+    }
+}
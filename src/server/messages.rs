@@ -1,21 +1,102 @@
 use crate::app::AppId;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use super::Clock;
 
 pub type MsgId = u64;
 pub type Date = u64;
+pub type Channel = String;
+pub type GroupId = u64;
+
+/// Channel every app joins on startup
+pub const DEFAULT_CHANNEL: &str = "#general";
+
+/// Information an app shares about itself
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Profile {
+    pub nickname: String,
+    /// Hex encoded X25519 key used to encrypt private messages for this app
+    pub public_key: String,
+    /// Hex encoded Ed25519 key used to check the signature of this app's messages
+    pub verifying_key: String,
+}
+
+/// Description of a file sent in pieces, as JSON encrypted like private messages
+/// when the file is sent to a single app
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileInfo {
+    /// Random id shared by the pieces of the file
+    pub transfer_id: u64,
+    pub name: String,
+    pub size: u64,
+    /// Number of pieces
+    pub chunks: u32,
+    /// Hex encoded SHA-256 digest of the whole file
+    pub checksum: String,
+}
+
+/// Piece of a file
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Chunk {
+    pub transfer_id: u64,
+    pub index: u32,
+    /// Hex encoded content, encrypted when the file is sent to a single app
+    pub data: String,
+    /// Hex encoded SHA-256 digest of `data` as sent, checked before decrypting it
+    pub checksum: String,
+}
 
 /// Header(Content)
 /// Defines message type
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum Header {
     Private(AppId, String),
-    Public(String),
-    Connection,
+    Group(GroupId, Vec<AppId>, HashMap<AppId, String>), // Members and content encrypted for each of them
+    Public(Channel, String),
+    Edit(MsgId, String),     // New content of a public message of the sender
+    Delete(MsgId),           // Public message of the sender to remove
+    Reaction(MsgId, String), // Public message and emoji or shortcode reacting to it
+    Connection(Profile),
     Disconnection,
+    ProfileUpdate(Profile),
+    IdProbe(u64),           // Nonce identifying the app checking that its id is free
+    IdTaken(u64),           // Nonce of the IdProbe colliding with the sender id
     SnapshotRequest(AppId), // AppId used to identify snapshot requester
     SnapshotResponse(AppId, Vec<Msg>),
+    SyncRequest(Clock), // Latest public message date of every sender seen by the app catching up
+    SyncResponse(AppId, Vec<Msg>, HashMap<AppId, Profile>), // Missed messages and profiles of their senders
+    Redelivery(AppId, Box<Msg>, Profile), // Private message held for an offline app, with its sender's profile
+    Delivered(AppId, MsgId), // Sender and id of a private message received by its recipient
+    Read(AppId, MsgId),      // Sender and id of a private message seen by its recipient
+    Typing,                  // The sender is writing a message, neither saved nor part of snapshots
+    FileStart(Option<AppId>, String), // Recipient, everyone if none, and description of a file
+    FileChunk(Option<AppId>, Chunk), // Recipient and piece of a file, neither saved nor part of snapshots
+}
+
+impl Header {
+    /// App a message is addressed to, `None` for messages meant for everyone
+    pub fn recipient(&self) -> Option<&AppId> {
+        match self {
+            Header::Private(app_id, _)
+            | Header::SyncResponse(app_id, _, _)
+            | Header::Redelivery(app_id, _, _)
+            | Header::Read(app_id, _)
+            | Header::FileStart(Some(app_id), _)
+            | Header::FileChunk(Some(app_id), _) => Some(app_id),
+            _ => None,
+        }
+    }
+
+    /// Public message an edit, a deletion or a reaction applies to
+    pub fn target(&self) -> Option<MsgId> {
+        match self {
+            Header::Edit(msg_id, _) | Header::Delete(msg_id) | Header::Reaction(msg_id, _) => {
+                Some(*msg_id)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -24,6 +105,16 @@ pub struct Msg {
     pub sender_id: AppId,
     pub header: Header,
     pub clock: Clock,
+    /// Message this one answers, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<MsgId>,
+    /// Wall-clock time of the originator when the message was created,
+    /// in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Signature of the originator, empty if unsigned
+    #[serde(default)]
+    pub signature: String,
 }
 
 impl Msg {
@@ -33,8 +124,27 @@ impl Msg {
             sender_id,
             header,
             clock,
+            reply_to: None,
+            timestamp: None,
+            signature: String::new(),
         }
     }
+    /// Bytes covered by the signature. The clock is left out
+    /// since it is updated by every app forwarding the message.
+    /// Going through a `Value` sorts the keys of the maps the header may contain,
+    /// so that every app computes the same bytes.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let signed = (
+            self.id,
+            &self.sender_id,
+            &self.header,
+            self.reply_to,
+            self.timestamp,
+        );
+        serde_json::to_value(signed)
+            .and_then(|value| serde_json::to_vec(&value))
+            .expect("Could not serialize message header")
+    }
     pub fn serialize(&self) -> serde_json::Result<String> {
         serde_json::to_string(self)
     }
@@ -43,6 +153,13 @@ impl Msg {
     }
 }
 
+/// Sorts messages in an order compatible with causality: if `a` happened before `b`
+/// every entry of `a`'s clock is lower or equal to `b`'s and at least one is lower,
+/// thus the sum of `a`'s dates is lower
+pub fn sort_causally(messages: &mut [Msg]) {
+    messages.sort_by_key(|msg| (msg.clock.values().sum::<Date>(), msg.id));
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -53,17 +170,22 @@ mod tests {
         let msg = Msg {
             id: 1,
             sender_id: "asdasdw".to_owned(),
-            header: Header::Private("42".to_string(), "I like trains !".to_string()),
+            header: Header::Private("42".to_string(), "I like\ntrains !".to_string()),
             clock: Clock(
                 [("1".to_string(), 2), ("3".to_string(), 4)]
                     .iter()
                     .cloned()
                     .collect(),
             ),
+            reply_to: Some(3),
+            timestamp: Some(1_561_939_200_000),
+            signature: "c0ffee".to_owned(),
         };
 
         let serialized = msg.serialize().expect("failed to serialize");
         println!("serialized = {}", serialized);
+        // Frames are separated by newlines
+        assert!(!serialized.contains('\n'));
 
         // Convert the JSON string back to a Msg.
         let deserialized = Msg::from_str(&serialized).expect("failed to deserialize");
@@ -72,4 +194,30 @@ mod tests {
 
         assert_eq!(msg, deserialized);
     }
+
+    #[test]
+    fn causal_sort() {
+        let msg = |id: MsgId, dates: &[(&str, Date)]| {
+            Msg::new(
+                id,
+                "asdasdw".to_owned(),
+                Header::Public(DEFAULT_CHANNEL.to_owned(), "I like trains !".to_owned()),
+                Clock(
+                    dates
+                        .iter()
+                        .map(|(id, date)| (id.to_string(), *date))
+                        .collect(),
+                ),
+            )
+        };
+        let mut messages = vec![
+            msg(1, &[("a", 3), ("b", 2)]),
+            msg(2, &[("a", 1)]),
+            msg(3, &[("a", 2), ("b", 2)]),
+            msg(4, &[("a", 1), ("b", 1)]),
+        ];
+        sort_causally(&mut messages);
+        let ids: Vec<MsgId> = messages.iter().map(|msg| msg.id).collect();
+        assert_eq!(ids, vec![2, 4, 3, 1]);
+    }
 }
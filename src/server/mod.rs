@@ -1,27 +1,58 @@
-use crate::app::AppId;
+use crate::app::{AppId, Receipt};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
+use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
 use shrinkwraprs::Shrinkwrap;
 
 pub mod messages;
-use messages::{Date, Header::*, Msg, MsgId};
+use messages::{
+    Channel, Chunk, Date, FileInfo, Header, Header::*, Msg, MsgId, Profile, DEFAULT_CHANNEL,
+};
 
 pub mod events;
 use events::{Event, Events};
 
+pub mod routing;
+use routing::Router;
+
+pub mod crypto;
+use crypto::Keys;
+
+pub mod mailbox;
+use mailbox::Mailbox;
+
+pub mod queue;
+use queue::ExpiringQueue;
+
+pub mod transfer;
+use transfer::{Download, CHUNK_SIZE, MAX_FILE_SIZE, TRANSFER_TIMEOUT};
+
+pub mod store;
+use ed25519_dalek::VerifyingKey;
+use store::{Identity, Record, Store};
+use x25519_dalek::PublicKey;
+
 use crate::app::events::Event as AppEvent;
 
+/// Number of local events between two saves of the clock when no message is saved,
+/// as many dates are skipped on restart in case they were lost
+const CLOCK_CHECKPOINT: Date = 64;
+/// Maximum number of revisions and reactions waiting for their message
+const PENDING_REVISIONS: usize = 256;
+/// Time after which a revision whose message never arrived is dropped
+const REVISION_TTL: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Shrinkwrap, Clone, Serialize, Deserialize, Debug, PartialEq)]
 #[shrinkwrap(mutable)]
 pub struct Clock(pub HashMap<AppId, Date>);
@@ -36,12 +67,65 @@ pub struct Snapshot {
     msg_history: Vec<Msg>,
 }
 
+/// Server options set from the command line
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Append a random suffix to the app id instead of aborting
+    /// when another connected app already uses it
+    pub rename_on_id_conflict: bool,
+    /// Secret shared by the members of the network, used to authenticate every frame
+    pub secret: Option<Vec<u8>>,
+    /// Directory where received files are saved
+    pub downloads_dir: PathBuf,
+}
+
 pub struct Server {
+    config: Config,
     app_id: AppId,
+    nickname: String,
+    /// Nicknames of the apps currently connected to the network
+    peers: HashMap<AppId, String>,
+    keys: Keys,
+    /// Public keys of every app met so far
+    public_keys: HashMap<AppId, PublicKey>,
+    /// Signature keys pinned the first time each app was met
+    verifying_keys: HashMap<AppId, VerifyingKey>,
+    /// Last profile of every app met so far
+    profiles: HashMap<AppId, Profile>,
+    router: Router,
     clock: Clock,
+    /// Number of unauthenticated frames received on each link
+    rejected_frames: HashMap<usize, usize>,
+    /// Nonce of the pending app id uniqueness challenge
+    id_probe: Option<u64>,
     sent_messages_ids: HashSet<MsgId>,
+    /// Messages dropped as forged or unverifiable, only relayed and reported once
+    dropped_ids: HashSet<MsgId>,
     snapshot: Snapshot,
     saved_messages: Vec<Msg>, //Saved messages - will be used to build snapshot
+    /// Channels joined by the user, public messages of the others are only relayed
+    channels: HashSet<Channel>,
+    /// Public messages sent or received in every channel, offered to the apps catching up
+    public_history: Vec<Msg>,
+    /// Revisions and reactions received before the message they apply to
+    pending_revisions: ExpiringQueue<Msg>,
+    /// Missed messages received while catching up, `None` when not catching up
+    backlog: Option<Vec<Msg>>,
+    /// Private messages waiting for their recipient to come back
+    mailbox: Mailbox,
+    /// Files being received, by transfer id
+    downloads: HashMap<u64, Download>,
+    /// Persistent storage, if a data directory was given
+    store: Option<Store>,
+}
+
+/// Outcome of the signature check of a distant message
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Authenticity {
+    Verified,
+    /// No signature key is known for the sender yet
+    UnknownSender,
+    Forged,
 }
 
 // Vector Clock implementation
@@ -93,7 +177,7 @@ impl Snapshot {
     }
     pub fn dump(&mut self, saving_date: Date) {
         let mut unique_messages = HashSet::new();
-        for (id, messages) in self.messages.to_owned() {
+        for (id, messages) in self.messages.clone() {
             let mut consistent_msgs = Vec::new();
             let local_sender_date = self
                 .dates
@@ -111,7 +195,7 @@ impl Snapshot {
                 }
             }
             self.messages.insert(id, consistent_msgs.clone());
-            for m in consistent_msgs.to_owned() {
+            for m in consistent_msgs {
                 if !(unique_messages.contains(&m.id)) {
                     unique_messages.insert(m.id);
                     self.msg_history.push(m);
@@ -120,7 +204,7 @@ impl Snapshot {
         }
         let self_id = self.local_id.clone();
 
-        // Sorting messages using vector clocks to build a consistent message history 
+        // Sorting messages using vector clocks to build a consistent message history
         self.msg_history.sort_by(|a, b| {
             // First, we sort by local date (date of the snapshot requester)
             if a.clock.get(&self_id).unwrap() == b.clock.get(&self_id).unwrap() {
@@ -137,19 +221,19 @@ impl Snapshot {
                     a.clock
                         .get(&b.sender_id)
                         .unwrap()
-                        .cmp(&b.clock.get(&b.sender_id).unwrap())
+                        .cmp(b.clock.get(&b.sender_id).unwrap())
                 } else {
                     // Otherwise the two messages have the same date
                     a.clock
                         .get(&self_id)
                         .unwrap()
-                        .cmp(&b.clock.get(&self_id).unwrap())
+                        .cmp(b.clock.get(&self_id).unwrap())
                 }
             } else {
                 a.clock
                     .get(&self_id)
                     .unwrap()
-                    .cmp(&b.clock.get(&self_id).unwrap())
+                    .cmp(b.clock.get(&self_id).unwrap())
             }
         });
 
@@ -194,39 +278,690 @@ impl Snapshot {
 }
 
 impl Server {
-    pub fn new(app_id: AppId) -> Self {
+    pub fn new(app_id: AppId, nickname: String, config: Config) -> Self {
         Server {
+            config,
             app_id: app_id.clone(),
+            nickname,
+            peers: HashMap::new(),
+            keys: Keys::generate(),
+            public_keys: HashMap::new(),
+            verifying_keys: HashMap::new(),
+            profiles: HashMap::new(),
+            router: Router::default(),
             clock: Clock::new(app_id.clone()),
+            rejected_frames: HashMap::new(),
+            id_probe: None,
             sent_messages_ids: HashSet::new(),
+            dropped_ids: HashSet::new(),
             snapshot: Snapshot::new(app_id),
             saved_messages: Vec::new(),
+            channels: [DEFAULT_CHANNEL.to_owned()].iter().cloned().collect(),
+            public_history: Vec::new(),
+            pending_revisions: ExpiringQueue::new(PENDING_REVISIONS, REVISION_TTL),
+            backlog: None,
+            mailbox: Mailbox::default(),
+            downloads: HashMap::new(),
+            store: None,
         }
     }
 
+    /// Reloads the identity, clock and messages saved in `store` by a previous run,
+    /// then keeps saving to it. The restored history is sent to the app.
+    pub fn restore(&mut self, mut store: Store, app_tx: &mpsc::Sender<AppEvent>) {
+        if let Some(identity) = store.identity() {
+            if identity.app_id == self.app_id {
+                match Keys::from_secret_hex(&identity.secret_keys) {
+                    Some(keys) => self.keys = keys,
+                    None => log::error!("Invalid secret keys in the identity file"),
+                }
+            }
+        }
+
+        let mut history = Vec::new();
+        let records = store.take_records();
+        let restored = !records.is_empty();
+        for record in records {
+            match record {
+                Record::Message(msg) => {
+                    self.clock.merge(&msg.clock);
+                    self.sent_messages_ids.insert(msg.id);
+                    match &msg.header {
+                        Private(app_id, _) if *app_id == self.app_id => {
+                            self.saved_messages.push(msg.clone())
+                        }
+                        Group(_, members, _) if members.contains(&self.app_id) => {
+                            self.saved_messages.push(msg.clone())
+                        }
+                        _ if msg.sender_id == self.app_id => self.saved_messages.push(msg.clone()),
+                        _ => {}
+                    }
+                    if is_public(&msg) {
+                        self.public_history.push(msg.clone());
+                    }
+                    history.push(msg);
+                }
+                Record::Clock(clock) => {
+                    self.clock.merge(&clock);
+                }
+                Record::Profile(app_id, profile) => {
+                    self.restore_profile(&app_id, &profile);
+                    send_to_app(AppEvent::PeerNickname(app_id, profile.nickname), app_tx);
+                }
+            }
+        }
+        // The clock continues from where it stopped, in case the id changed.
+        // Dates after the last save may have been lost in a crash, they are not reused
+        let date = self.clock.entry(self.app_id.clone()).or_insert(0);
+        if restored {
+            *date += CLOCK_CHECKPOINT;
+        }
+
+        let history = history
+            .iter()
+            .filter_map(|msg| match msg.header {
+                Private(_, _) | Group(_, _, _) => self.decrypt_private(msg),
+                _ if self.has_joined(msg) => Some(msg.clone()),
+                _ => None,
+            })
+            .collect();
+        send_to_app(AppEvent::History(history), app_tx);
+
+        self.store = Some(store);
+        self.save_identity();
+    }
+
+    fn save_identity(&self) {
+        if let Some(store) = &self.store {
+            store.save_identity(&Identity {
+                app_id: self.app_id.clone(),
+                nickname: self.nickname.clone(),
+                secret_keys: self.keys.to_secret_hex(),
+            });
+        }
+    }
+
+    fn store(&mut self, record: Record) {
+        if let Some(store) = &mut self.store {
+            store.append(&record);
+        }
+    }
+
+    /// Keeps a message sent or received by the user for snapshots and later runs
+    fn save_message(&mut self, msg: Msg) {
+        self.store(Record::Message(msg.clone()));
+        if is_public(&msg) {
+            self.public_history.push(msg.clone());
+        }
+        self.saved_messages.push(msg);
+    }
+
+    /// Channel of a public message, or of the message an edit, a deletion
+    /// or a reaction applies to
+    fn channel_of<'a>(&'a self, msg: &'a Msg) -> Option<&'a Channel> {
+        let original = match msg.header.target() {
+            Some(target) => self.public_history.iter().find(|m| m.id == target)?,
+            None => msg,
+        };
+        match &original.header {
+            Public(channel, _) => Some(channel),
+            _ => None,
+        }
+    }
+
+    /// Whether a public message, revision or reaction belongs to a channel joined by the user
+    fn has_joined(&self, msg: &Msg) -> bool {
+        self.channel_of(msg)
+            .is_some_and(|channel| self.channels.contains(channel))
+    }
+
+    /// Whether a revision applies to a public message of its own sender,
+    /// anyone can react to any public message.
+    /// `None` if that message has not been received yet
+    fn check_revision(&self, msg: &Msg, target: MsgId) -> Option<bool> {
+        let original = self
+            .public_history
+            .iter()
+            .find(|m| m.id == target && m.header.target().is_none())?;
+        match msg.header {
+            Reaction(_, _) => Some(true),
+            _ => Some(original.sender_id == msg.sender_id),
+        }
+    }
+
+    /// Revisions and reactions which were waiting for the message `msg_id`,
+    /// once checked against it
+    fn release_revisions(&mut self, msg_id: MsgId) -> Vec<Msg> {
+        let revisions = self
+            .pending_revisions
+            .take(|revision| revision.header.target() == Some(msg_id));
+        revisions
+            .into_iter()
+            .filter(|revision| self.check_revision(revision, msg_id) == Some(true))
+            .collect()
+    }
+
+    /// Saves a public message, revision or reaction in the public history
+    fn keep_public(&mut self, msg: &Msg) {
+        self.store(Record::Message(msg.clone()));
+        self.public_history.push(msg.clone());
+    }
+
+    /// Keeps a public message, revision or reaction from another app
+    /// and delivers it if the user joined its channel
+    fn accept_public(&mut self, msg: Msg, app_tx: &mpsc::Sender<AppEvent>) {
+        self.keep_public(&msg);
+        if self.has_joined(&msg) {
+            send_to_app(AppEvent::DistantMessage(msg), app_tx);
+        }
+    }
+
+    /// Latest date of every sender among the public messages kept, which is
+    /// all an app has seen of them, dropped messages being left out
+    fn public_dates(&self) -> Clock {
+        let mut dates = Clock(HashMap::new());
+        for msg in &self.public_history {
+            let date = msg.clock.get(&msg.sender_id).cloned().unwrap_or(0);
+            let latest = dates.entry(msg.sender_id.clone()).or_insert(0);
+            *latest = (*latest).max(date);
+        }
+        dates
+    }
+
+    /// Public messages that an app whose latest dates are `dates` has not seen yet
+    fn missing_messages(&self, dates: &Clock) -> Vec<Msg> {
+        self.public_history
+            .iter()
+            .filter(|msg| {
+                let date = msg.clock.get(&msg.sender_id).cloned().unwrap_or(0);
+                date > dates.get(&msg.sender_id).cloned().unwrap_or(0)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Delivers the messages missed before joining, in causal order
+    fn deliver_backlog(&mut self, app_tx: &mpsc::Sender<AppEvent>) {
+        let mut backlog = match self.backlog.take() {
+            Some(backlog) => backlog,
+            None => return,
+        };
+        messages::sort_causally(&mut backlog);
+        let mut delivered = Vec::new();
+        for msg in backlog {
+            // Revisions come after the message they apply to in causal order
+            if let Some(target) = msg.header.target() {
+                if self.check_revision(&msg, target) != Some(true) {
+                    log::warn!("dropped invalid backlog revision {:?}", msg);
+                    continue;
+                }
+            }
+            self.clock.merge(&msg.clock);
+            self.keep_public(&msg);
+            let msg_id = msg.id;
+            delivered.push(msg);
+            // Revisions and reactions received live before this message
+            for revision in self.release_revisions(msg_id) {
+                self.keep_public(&revision);
+                delivered.push(revision);
+            }
+        }
+        self.store(Record::Clock(self.clock.clone()));
+        delivered.retain(|msg| self.has_joined(msg));
+        if !delivered.is_empty() {
+            send_to_app(AppEvent::Backlog(delivered), app_tx);
+        }
+    }
+
+    /// Sends the private messages held for `recipient`, who just came back.
+    /// They are wrapped in a new message since the apps on the way have already seen them.
+    fn redeliver(
+        &mut self,
+        recipient: &AppId,
+        outputs: &mut [File],
+        app_tx: &mpsc::Sender<AppEvent>,
+    ) {
+        for held in self.mailbox.pending_for(recipient) {
+            // The recipient needs the key of the sender to decrypt the message
+            let profile = if held.sender_id == self.app_id {
+                self.profile()
+            } else if let Some(profile) = self.profiles.get(&held.sender_id) {
+                profile.clone()
+            } else {
+                continue;
+            };
+            let msg = self.new_message(Redelivery(recipient.clone(), Box::new(held), profile));
+            self.send_message(&msg, outputs, app_tx);
+        }
+    }
+
+    /// Copy of a private or group message sent or received by this app with its content
+    /// decrypted, fails if the key of the other end is unknown or the content invalid.
+    /// The decrypted content of a group message is the one of this app.
+    fn decrypt_private(&self, msg: &Msg) -> Option<Msg> {
+        match &msg.header {
+            Private(app_id, content) => {
+                let correspondent = if msg.sender_id == self.app_id {
+                    app_id
+                } else {
+                    &msg.sender_id
+                };
+                let public_key = self.public_keys.get(correspondent)?;
+                let mut decrypted = msg.clone();
+                decrypted.header = Private(app_id.clone(), self.keys.decrypt(public_key, content)?);
+                Some(decrypted)
+            }
+            Group(group_id, members, contents) => {
+                // Our own messages are decrypted from the copy of any other member
+                let (correspondent, content) = if msg.sender_id == self.app_id {
+                    contents
+                        .iter()
+                        .find(|(app_id, _)| self.public_keys.contains_key(*app_id))?
+                } else {
+                    (&msg.sender_id, contents.get(&self.app_id)?)
+                };
+                let public_key = self.public_keys.get(correspondent)?;
+                let mut plaintext = HashMap::new();
+                plaintext.insert(self.app_id.clone(), self.keys.decrypt(public_key, content)?);
+                let mut decrypted = msg.clone();
+                decrypted.header = Group(*group_id, members.clone(), plaintext);
+                Some(decrypted)
+            }
+            _ => None,
+        }
+    }
+
+    /// Sends a file in pieces to `recipient`, or to everyone,
+    /// the description and pieces of a file sent to a single app are encrypted
+    fn send_file(
+        &mut self,
+        path: &Path,
+        recipient: Option<AppId>,
+        outputs: &mut [File],
+        app_tx: &mpsc::Sender<AppEvent>,
+    ) -> Result<FileInfo, String> {
+        let public_key = match &recipient {
+            Some(app_id) => Some(
+                *self
+                    .public_keys
+                    .get(app_id)
+                    .ok_or_else(|| format!("Unknown key for {}, the file was not sent", app_id))?,
+            ),
+            None => None,
+        };
+        let unreadable = |e: std::io::Error| format!("Could not read {}: {}", path.display(), e);
+        if fs::metadata(path).map_err(unreadable)?.len() > MAX_FILE_SIZE {
+            return Err(format!(
+                "{} is too large, files are limited to {} MiB",
+                path.display(),
+                MAX_FILE_SIZE / (1024 * 1024)
+            ));
+        }
+        let data = fs::read(path).map_err(unreadable)?;
+
+        let info = FileInfo {
+            transfer_id: thread_rng().gen(),
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "download".to_owned()),
+            size: data.len() as u64,
+            chunks: data.chunks(CHUNK_SIZE).count() as u32,
+            checksum: transfer::checksum(&data),
+        };
+        let description =
+            serde_json::to_string(&info).map_err(|e| format!("Could not send the file: {}", e))?;
+        let msg = self.new_message(FileStart(
+            recipient.clone(),
+            self.encrypt_for(public_key.as_ref(), description),
+        ));
+        self.send_message(&msg, outputs, app_tx);
+
+        for (index, piece) in data.chunks(CHUNK_SIZE).enumerate() {
+            let data = self.encrypt_for(public_key.as_ref(), hex::encode(piece));
+            let chunk = Chunk {
+                transfer_id: info.transfer_id,
+                index: index as u32,
+                checksum: transfer::checksum(data.as_bytes()),
+                data,
+            };
+            let msg = self.new_message(FileChunk(recipient.clone(), chunk));
+            self.send_message(&msg, outputs, app_tx);
+        }
+        Ok(info)
+    }
+
+    /// `content` encrypted for the owner of `public_key`, as is without one
+    fn encrypt_for(&self, public_key: Option<&PublicKey>, content: String) -> String {
+        match public_key {
+            Some(public_key) => self.keys.encrypt(public_key, &content),
+            None => content,
+        }
+    }
+
+    /// Description of a file sent by `sender_id`, fails if it cannot be decrypted or parsed
+    fn file_info(&self, sender_id: &AppId, encrypted: bool, description: &str) -> Option<FileInfo> {
+        let description = if encrypted {
+            let public_key = self.public_keys.get(sender_id)?;
+            self.keys.decrypt(public_key, description)?
+        } else {
+            description.to_owned()
+        };
+        serde_json::from_str(&description).ok()
+    }
+
+    /// Content of a piece of file sent by `sender_id`,
+    /// fails if it does not match its checksum or cannot be decrypted
+    fn chunk_content(&self, sender_id: &AppId, encrypted: bool, chunk: &Chunk) -> Option<Vec<u8>> {
+        if transfer::checksum(chunk.data.as_bytes()) != chunk.checksum {
+            return None;
+        }
+        let content = if encrypted {
+            let public_key = self.public_keys.get(sender_id)?;
+            self.keys.decrypt(public_key, &chunk.data)?
+        } else {
+            chunk.data.clone()
+        };
+        hex::decode(content).ok()
+    }
+
+    /// Download of a file from `sender_id`, started if needed along with a timer
+    /// dropping it if the transfer stalls
+    fn download(
+        &mut self,
+        transfer_id: u64,
+        sender_id: &AppId,
+        self_tx: &mpsc::Sender<Event>,
+    ) -> &mut Download {
+        if !self.downloads.contains_key(&transfer_id) {
+            watch_download(transfer_id, TRANSFER_TIMEOUT, self_tx);
+        }
+        self.downloads
+            .entry(transfer_id)
+            .or_insert_with(|| Download::new(sender_id.clone()))
+    }
+
+    /// Reports the progress of a download to the app and saves the file once complete
+    fn update_download(&mut self, transfer_id: u64, app_tx: &mpsc::Sender<AppEvent>) {
+        let download = match self.downloads.get(&transfer_id) {
+            Some(download) => download,
+            None => return,
+        };
+        let info = match &download.info {
+            Some(info) => info,
+            None => return,
+        };
+        send_to_app(
+            AppEvent::FileProgress(
+                transfer_id,
+                download.sender_id.clone(),
+                info.name.clone(),
+                download.received(),
+                info.chunks as usize,
+            ),
+            app_tx,
+        );
+        let result = match download.assemble() {
+            Some(result) => result,
+            None => return,
+        };
+
+        let name = info.name.clone();
+        let sender = self
+            .peers
+            .get(&download.sender_id)
+            .cloned()
+            .unwrap_or_else(|| download.sender_id.clone());
+        self.downloads.remove(&transfer_id);
+        send_to_app(AppEvent::FileDone(transfer_id), app_tx);
+
+        let event = match result.and_then(|data| {
+            transfer::save(&self.config.downloads_dir, &name, &data)
+                .map_err(|e| format!("Could not save {}: {}", name, e))
+        }) {
+            Ok(path) => AppEvent::ServerMessage(format!(
+                "Received {} from {}, saved to {}",
+                name,
+                sender,
+                path.display()
+            )),
+            Err(error) => AppEvent::ServerError(format!("{}, received from {}", error, sender)),
+        };
+        send_to_app(event, app_tx);
+    }
+
     fn get_date(&self) -> Date {
         *self.clock.get(&self.app_id).expect("missing local app_id")
     }
 
+    /// Moves the local clock to the next event, saved with the messages stored
+    /// and every `CLOCK_CHECKPOINT` events
     fn increment_clock(&mut self) {
         let date = self.clock.entry(self.app_id.to_owned()).or_insert(0);
         *date += 1;
+        if date.is_multiple_of(CLOCK_CHECKPOINT) {
+            self.store(Record::Clock(self.clock.clone()));
+        }
     }
 
-    fn send_message(&mut self, msg: &Msg, output_file: &mut File, app_tx: &mpsc::Sender<AppEvent>) {
-        if let Ok(msg_str) = msg.serialize() {
-            if let Ok(_) = output_file.write_all(format!("{}\n", msg_str).as_bytes()) {
-                log::info!(
-                    "sent, local date: {}, messsage: {:?}",
-                    self.get_date(),
-                    msg.header
-                );
-            } else {
+    /// Returns the id of another live app already using `nickname`, if any
+    fn nickname_owner(&self, nickname: &str, claimant: &AppId) -> Option<AppId> {
+        if self.nickname == nickname && *claimant != self.app_id {
+            return Some(self.app_id.clone());
+        }
+        self.peers
+            .iter()
+            .find(|(id, nick)| *nick == nickname && *id != claimant)
+            .map(|(id, _)| id.clone())
+    }
+
+    fn profile(&self) -> Profile {
+        Profile {
+            nickname: self.nickname.clone(),
+            public_key: crypto::encode_public_key(&self.keys.public),
+            verifying_key: crypto::encode_verifying_key(&self.keys.verifying),
+        }
+    }
+
+    /// Checks the signature of a distant message against the key pinned for its sender,
+    /// or against the announced key for the first profile of an app.
+    /// Id probes are signed by whoever claims the id, which is precisely what they check,
+    /// so they are never compared with the pinned key and always forwarded
+    fn authenticate(&self, msg: &Msg) -> Authenticity {
+        let verifying_key = match (self.verifying_keys.get(&msg.sender_id), &msg.header) {
+            (_, IdProbe(_)) => return Authenticity::UnknownSender,
+            (Some(verifying_key), _) => *verifying_key,
+            (None, Connection(profile)) | (None, ProfileUpdate(profile)) => {
+                match crypto::decode_verifying_key(&profile.verifying_key) {
+                    Some(verifying_key) => verifying_key,
+                    None => return Authenticity::Forged,
+                }
+            }
+            (None, _) => return Authenticity::UnknownSender,
+        };
+        if crypto::verify(&verifying_key, &msg.signed_bytes(), &msg.signature) {
+            Authenticity::Verified
+        } else {
+            Authenticity::Forged
+        }
+    }
+
+    /// Records the profile of a distant app and warns the user about conflicts
+    fn set_peer_profile(
+        &mut self,
+        app_id: &AppId,
+        profile: &Profile,
+        app_tx: &mpsc::Sender<AppEvent>,
+    ) {
+        let nickname = &profile.nickname;
+        if let Some(owner) = self.nickname_owner(nickname, app_id) {
+            send_to_app(
+                AppEvent::ServerError(format!(
+                    "Nickname conflict: {} is claimed by both {} and {}",
+                    nickname, owner, app_id
+                )),
+                app_tx,
+            );
+        }
+        match self.peers.insert(app_id.clone(), nickname.to_owned()) {
+            Some(ref old) if old != nickname => {
                 send_to_app(
-                    AppEvent::ServerMessage("No one can hear you".to_owned()),
+                    AppEvent::ServerMessage(format!("{} is now known as {}", old, nickname)),
                     app_tx,
                 );
-                log::error!("Failed to write to output file");
+            }
+            _ => {}
+        }
+        send_to_app(
+            AppEvent::PeerNickname(app_id.clone(), nickname.to_owned()),
+            app_tx,
+        );
+
+        self.store(Record::Profile(app_id.clone(), profile.clone()));
+
+        if let Some(public_key) = crypto::decode_public_key(&profile.public_key) {
+            match self.public_keys.insert(app_id.clone(), public_key) {
+                Some(old) if old != public_key => {
+                    send_to_app(
+                        AppEvent::ServerMessage(format!(
+                            "The public key of {} changed, new fingerprint: {}",
+                            nickname,
+                            crypto::fingerprint(public_key.as_bytes())
+                        )),
+                        app_tx,
+                    );
+                }
+                _ => {}
+            }
+        } else {
+            log::error!(
+                "Invalid public key `{}` from {}",
+                profile.public_key,
+                app_id
+            );
+        }
+
+        self.restore_profile(app_id, profile);
+    }
+
+    /// Updates the keys of a distant app from its profile
+    fn restore_profile(&mut self, app_id: &AppId, profile: &Profile) {
+        self.profiles.insert(app_id.clone(), profile.clone());
+        if let Some(public_key) = crypto::decode_public_key(&profile.public_key) {
+            self.public_keys.insert(app_id.clone(), public_key);
+        }
+        // Trust on first use, later profiles are checked against this key
+        if let Some(verifying_key) = crypto::decode_verifying_key(&profile.verifying_key) {
+            self.verifying_keys
+                .entry(app_id.clone())
+                .or_insert(verifying_key);
+        }
+    }
+
+    /// Asks the network whether our app id is already in use.
+    /// The connection is announced once the probe times out unanswered.
+    fn send_id_probe(
+        &mut self,
+        outputs: &mut [File],
+        app_tx: &mpsc::Sender<AppEvent>,
+        self_tx: &mpsc::Sender<Event>,
+    ) {
+        let mut rng = thread_rng();
+        let nonce: u64 = rng.gen();
+        self.id_probe = Some(nonce);
+
+        let msg_id: MsgId = rng.gen();
+        self.sent_messages_ids.insert(msg_id);
+        // The probe is sent with an empty clock so that it does not pollute
+        // the clock entry of the app already owning this id
+        let mut msg = Msg::new(
+            msg_id,
+            self.app_id.clone(),
+            IdProbe(nonce),
+            Clock(HashMap::new()),
+        );
+        msg.signature = self.keys.sign(&msg.signed_bytes());
+        self.send_message(&msg, outputs, app_tx);
+
+        let self_tx = self_tx.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(1));
+            self_tx.send(Event::IdProbeTimeout(nonce)).unwrap();
+        });
+    }
+
+    /// Switches to a new app id, only valid before the connection is announced
+    fn rename(&mut self, app_id: AppId) {
+        if self.nickname == self.app_id {
+            self.nickname = app_id.clone();
+        }
+        let date = self.clock.remove(&self.app_id).unwrap_or(0);
+        self.clock.insert(app_id.clone(), date);
+        self.snapshot = Snapshot::new(app_id.clone());
+        self.app_id = app_id;
+        self.save_identity();
+    }
+
+    /// Creates a new message from this app, signed and dated with the local clock
+    fn new_message(&mut self, header: Header) -> Msg {
+        self.new_reply(header, None)
+    }
+
+    /// Creates a new message from this app answering `reply_to`
+    fn new_reply(&mut self, header: Header, reply_to: Option<MsgId>) -> Msg {
+        let msg_id: MsgId = thread_rng().gen();
+        self.sent_messages_ids.insert(msg_id);
+        self.increment_clock();
+        let mut msg = Msg::new(msg_id, self.app_id.clone(), header, self.clock.clone());
+        msg.reply_to = reply_to;
+        msg.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|elapsed| elapsed.as_millis() as u64);
+        msg.signature = self.keys.sign(&msg.signed_bytes());
+        msg
+    }
+
+    /// Sends a message created by this app
+    fn send_message(&mut self, msg: &Msg, outputs: &mut [File], app_tx: &mpsc::Sender<AppEvent>) {
+        self.route_message(msg, None, outputs, app_tx);
+    }
+
+    /// Writes a message to the links leading to its recipient:
+    /// private messages follow the learned route if any, everything else is flooded
+    fn route_message(
+        &mut self,
+        msg: &Msg,
+        from: Option<usize>,
+        outputs: &mut [File],
+        app_tx: &mpsc::Sender<AppEvent>,
+    ) {
+        let links = self
+            .router
+            .links(msg.header.recipient(), from, outputs.len());
+
+        if let Ok(mut msg_str) = msg.serialize() {
+            if let Some(secret) = &self.config.secret {
+                msg_str = crypto::seal_frame(secret, &msg_str);
+            }
+            for link in links {
+                if outputs[link]
+                    .write_all(format!("{}\n", msg_str).as_bytes())
+                    .is_ok()
+                {
+                    log::info!(
+                        "sent on link {}, local date: {}, messsage: {:?}",
+                        link,
+                        self.get_date(),
+                        msg.header
+                    );
+                } else {
+                    send_to_app(
+                        AppEvent::ServerError("No one can hear you".to_owned()),
+                        app_tx,
+                    );
+                    log::error!("Failed to write to output file {}", link);
+                }
             }
         } else {
             log::error!("Could not serialize `{:?}`", msg);
@@ -236,57 +971,79 @@ impl Server {
     fn receive_message(
         &mut self,
         msg: &mut Msg,
-        output_file: &mut File,
+        link: usize,
+        outputs: &mut [File],
         app_tx: &mpsc::Sender<AppEvent>,
     ) {
         self.clock.merge(&msg.clock);
+        self.router.learn(&msg.sender_id, link);
         log::info!(
-            "received, local date: {}, messsage: {:?}",
+            "received on link {}, local date: {}, messsage: {:?}",
+            link,
             self.get_date(),
             msg.header
         );
         msg.clock = self.clock.clone();
-        self.send_message(msg, output_file, app_tx);
+        // Messages addressed to this app stop here
+        if msg.header.recipient() != Some(&self.app_id) {
+            self.route_message(msg, Some(link), outputs, app_tx);
+        }
     }
 }
 
+/// Whether a message is part of the public history:
+/// public messages, their revisions and the reactions to them
+fn is_public(msg: &Msg) -> bool {
+    matches!(msg.header, Public(_, _)) || msg.header.target().is_some()
+}
+
 pub fn send_to_app(msg: AppEvent, app_tx: &mpsc::Sender<AppEvent>) {
     app_tx.send(msg).expect("Could not send message to the app");
 }
 
+/// Checks on a download once `delay` has elapsed
+fn watch_download(transfer_id: u64, delay: Duration, self_tx: &mpsc::Sender<Event>) {
+    let self_tx = self_tx.clone();
+    thread::spawn(move || {
+        thread::sleep(delay);
+        self_tx.send(Event::DownloadTimeout(transfer_id)).unwrap();
+    });
+}
+
 pub fn run(
     mut server: Server,
     app_rx: mpsc::Receiver<Event>,
     app_tx: mpsc::Sender<AppEvent>,
-    input_file_path: PathBuf,
-    output_file_path: PathBuf,
+    input_file_paths: Vec<PathBuf>,
+    output_file_paths: Vec<PathBuf>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Channel to asynchronously speak to itself
     let (self_tx, server_rx) = mpsc::channel();
 
     // 1 Setup event handlers
-    let events = Events::new(input_file_path.to_owned(), app_rx, server_rx);
+    let events = Events::new(
+        input_file_paths,
+        server.config.secret.clone(),
+        app_rx,
+        server_rx,
+    );
 
-    // 2 Open the output pipe,
-    // the program will freeze until there is someone at the other end
-    let mut output_file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(output_file_path.to_owned())
-        .expect("failed to open output file");
+    // 2 Open the output pipes,
+    // the program will freeze until there is someone at the other end of each
+    let mut outputs = Vec::new();
+    for output_file_path in &output_file_paths {
+        outputs.push(
+            OpenOptions::new()
+                .append(true)
+                .open(output_file_path)
+                .expect("failed to open output file"),
+        );
+    }
 
     let mut rng = thread_rng();
 
-    let msg_id: MsgId = rng.gen();
-    server.sent_messages_ids.insert(msg_id.clone());
-    server.increment_clock();
-    let msg = Msg::new(
-        msg_id,
-        server.app_id.clone(),
-        Connection,
-        server.clock.clone(),
-    );
-    server.send_message(&msg, &mut output_file, &app_tx);
+    // 3 Make sure no one else uses our id before announcing ourselves
+    server.send_id_probe(&mut outputs, &app_tx, &self_tx);
 
     let mut is_waiting_for_snapshot = false;
 
@@ -295,62 +1052,291 @@ pub fn run(
         match events.next()? {
             // User / Server commands
             //-----------------------
-            Event::UserPublicMessage(message) => {
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    Public(message),
-                    server.clock.clone(),
-                );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                server.saved_messages.push(msg);
+            Event::UserPublicMessage(channel, message) => {
+                let msg = server.new_message(Public(channel, message));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                send_to_app(AppEvent::Sent(msg.clone()), &app_tx);
+                server.save_message(msg);
+            }
+            Event::UserReply(parent, channel, message) => {
+                let msg = server.new_reply(Public(channel, message), Some(parent));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                send_to_app(AppEvent::Sent(msg.clone()), &app_tx);
+                server.save_message(msg);
             }
             Event::UserPrivateMessage(app_id, message) => {
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    Private(app_id, message),
-                    server.clock.clone(),
+                let content = match server.public_keys.get(&app_id) {
+                    Some(public_key) => server.keys.encrypt(public_key, &message),
+                    None => {
+                        send_to_app(
+                            AppEvent::ServerError(format!(
+                                "Unknown public key for {}, message not sent",
+                                app_id
+                            )),
+                            &app_tx,
+                        );
+                        continue;
+                    }
+                };
+                let msg = server.new_message(Private(app_id.clone(), content));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                let mut sent = msg.clone();
+                sent.header = Private(app_id.clone(), message);
+                send_to_app(AppEvent::Sent(sent), &app_tx);
+                if app_id != server.app_id && !server.peers.contains_key(&app_id) {
+                    let name = server
+                        .profiles
+                        .get(&app_id)
+                        .map_or(app_id.clone(), |profile| profile.nickname.clone());
+                    send_to_app(
+                        AppEvent::ServerMessage(format!(
+                            "{} is offline, the message will be delivered when they come back",
+                            name
+                        )),
+                        &app_tx,
+                    );
+                    server.mailbox.hold(&app_id, msg.clone());
+                }
+                server.save_message(msg);
+            }
+            Event::SendFile(path, recipient) => {
+                match server.send_file(&path, recipient.clone(), &mut outputs, &app_tx) {
+                    Ok(info) => send_to_app(
+                        AppEvent::ServerMessage(format!(
+                            "Sent {} ({} bytes) to {}",
+                            info.name,
+                            info.size,
+                            match &recipient {
+                                Some(app_id) => server
+                                    .peers
+                                    .get(app_id)
+                                    .cloned()
+                                    .unwrap_or_else(|| app_id.clone()),
+                                None => "everyone".to_owned(),
+                            }
+                        )),
+                        &app_tx,
+                    ),
+                    Err(error) => send_to_app(AppEvent::ServerError(error), &app_tx),
+                }
+            }
+            Event::JoinChannel(channel) => {
+                if server.channels.insert(channel.clone()) {
+                    // Show what was said there before
+                    let history = server
+                        .public_history
+                        .iter()
+                        .filter(|msg| server.channel_of(msg) == Some(&channel))
+                        .cloned()
+                        .collect();
+                    send_to_app(AppEvent::History(history), &app_tx);
+                }
+            }
+            Event::PartChannel(channel) => {
+                server.channels.remove(&channel);
+            }
+            Event::UserTyping => {
+                let msg = server.new_message(Typing);
+                server.send_message(&msg, &mut outputs, &app_tx);
+            }
+            Event::MarkRead(read) => {
+                for (sender_id, msg_id) in read {
+                    let msg = server.new_message(Read(sender_id, msg_id));
+                    server.send_message(&msg, &mut outputs, &app_tx);
+                }
+            }
+            Event::UserGroupMessage(group_id, members, message) => {
+                let mut contents = HashMap::new();
+                let mut unknown = Vec::new();
+                for member in members.iter().filter(|m| **m != server.app_id) {
+                    match server.public_keys.get(member) {
+                        Some(public_key) => {
+                            contents
+                                .insert(member.clone(), server.keys.encrypt(public_key, &message));
+                        }
+                        None => unknown.push(member.clone()),
+                    }
+                }
+                if !unknown.is_empty() {
+                    send_to_app(
+                        AppEvent::ServerError(format!(
+                            "Unknown public key for {}, message not sent",
+                            unknown.join(", ")
+                        )),
+                        &app_tx,
+                    );
+                    continue;
+                }
+                let msg = server.new_message(Group(group_id, members.clone(), contents));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                let mut sent = msg.clone();
+                let mut plaintext = HashMap::new();
+                plaintext.insert(server.app_id.clone(), message);
+                sent.header = Group(group_id, members, plaintext);
+                send_to_app(AppEvent::Sent(sent), &app_tx);
+                server.save_message(msg);
+            }
+            Event::EditMessage(target, _) | Event::DeleteMessage(target)
+                if !server.public_history.iter().any(|m| {
+                    m.id == target && m.sender_id == server.app_id && m.header.target().is_none()
+                }) =>
+            {
+                send_to_app(
+                    AppEvent::ServerError(
+                        "Only your own public messages can be changed".to_owned(),
+                    ),
+                    &app_tx,
                 );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                server.saved_messages.push(msg);
+            }
+            Event::EditMessage(target, content) => {
+                let msg = server.new_message(Edit(target, content));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                send_to_app(AppEvent::Sent(msg.clone()), &app_tx);
+                server.save_message(msg);
+            }
+            Event::DeleteMessage(target) => {
+                let msg = server.new_message(Delete(target));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                send_to_app(AppEvent::Sent(msg.clone()), &app_tx);
+                server.save_message(msg);
+            }
+            Event::React(target, emoji) => {
+                if server.public_history.iter().any(|m| m.id == target) {
+                    let msg = server.new_message(Reaction(target, emoji));
+                    server.send_message(&msg, &mut outputs, &app_tx);
+                    send_to_app(AppEvent::Sent(msg.clone()), &app_tx);
+                    server.save_message(msg);
+                } else {
+                    send_to_app(
+                        AppEvent::ServerError("Only public messages can get reactions".to_owned()),
+                        &app_tx,
+                    );
+                }
+            }
+            Event::SetNickname(nickname) => {
+                if let Some(owner) = server.nickname_owner(&nickname, &server.app_id) {
+                    send_to_app(
+                        AppEvent::ServerError(format!(
+                            "Nickname {} is already used by {}",
+                            nickname, owner
+                        )),
+                        &app_tx,
+                    );
+                    continue;
+                }
+                server.nickname = nickname.clone();
+                server.save_identity();
+
+                let msg = server.new_message(ProfileUpdate(server.profile()));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                send_to_app(
+                    AppEvent::PeerNickname(server.app_id.clone(), nickname),
+                    &app_tx,
+                );
+            }
+            Event::IdProbeTimeout(nonce) => {
+                if server.id_probe == Some(nonce) {
+                    server.id_probe = None;
+
+                    let msg = server.new_message(Connection(server.profile()));
+                    server.send_message(&msg, &mut outputs, &app_tx);
+
+                    // Ask for the messages sent while we were away
+                    server.backlog = Some(Vec::new());
+                    let dates = server.public_dates();
+                    let msg = server.new_message(SyncRequest(dates));
+                    server.send_message(&msg, &mut outputs, &app_tx);
+
+                    let self_tx = self_tx.clone();
+                    thread::spawn(move || {
+                        thread::sleep(Duration::from_secs(2));
+                        self_tx.send(Event::SyncTimeout).unwrap();
+                    });
+                }
+            }
+            Event::SyncTimeout => {
+                server.deliver_backlog(&app_tx);
+            }
+            Event::DownloadTimeout(transfer_id) => {
+                let idle = match server.downloads.get(&transfer_id) {
+                    Some(download) => download.idle(),
+                    None => continue,
+                };
+                // Pieces arrived since the timer was set
+                if idle < TRANSFER_TIMEOUT {
+                    watch_download(transfer_id, TRANSFER_TIMEOUT - idle, &self_tx);
+                    continue;
+                }
+                if let Some(download) = server.downloads.remove(&transfer_id) {
+                    let sender = server
+                        .peers
+                        .get(&download.sender_id)
+                        .cloned()
+                        .unwrap_or(download.sender_id);
+                    let name = download.info.map_or("a file".to_owned(), |info| info.name);
+                    send_to_app(AppEvent::FileDone(transfer_id), &app_tx);
+                    send_to_app(
+                        AppEvent::ServerError(format!(
+                            "The transfer of {} from {} stalled and was aborted",
+                            name, sender
+                        )),
+                        &app_tx,
+                    );
+                }
+            }
+            Event::GetKeys => {
+                let mut app_ids = vec![server.app_id.clone()];
+                app_ids.extend(server.public_keys.keys().cloned());
+                let fingerprints = app_ids
+                    .into_iter()
+                    .map(|app_id| {
+                        let (public_key, verifying_key) = if app_id == server.app_id {
+                            (Some(&server.keys.public), Some(&server.keys.verifying))
+                        } else {
+                            (
+                                server.public_keys.get(&app_id),
+                                server.verifying_keys.get(&app_id),
+                            )
+                        };
+                        let fingerprint = format!(
+                            "encryption {}, signature {}",
+                            public_key.map_or("unknown".to_owned(), |key| {
+                                crypto::fingerprint(key.as_bytes())
+                            }),
+                            verifying_key.map_or("unknown".to_owned(), |key| {
+                                crypto::fingerprint(key.as_bytes())
+                            }),
+                        );
+                        (app_id, fingerprint)
+                    })
+                    .collect();
+                send_to_app(AppEvent::DisplayKeys(fingerprints), &app_tx);
+            }
+            Event::GetPeers => {
+                let mut peers: Vec<(AppId, String)> = server
+                    .peers
+                    .iter()
+                    .filter(|(app_id, _)| **app_id != server.app_id)
+                    .map(|(app_id, nickname)| (app_id.clone(), nickname.clone()))
+                    .collect();
+                peers.sort_by(|a, b| a.1.cmp(&b.1));
+                send_to_app(AppEvent::DisplayPeers(peers), &app_tx);
             }
             Event::GetClock => {
                 send_to_app(AppEvent::DisplayClock(server.clock.clone()), &app_tx);
             }
             Event::Shutdown => {
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    Disconnection,
-                    server.clock.clone(),
-                );
-                server.send_message(&msg, &mut output_file, &app_tx);
+                let msg = server.new_message(Disconnection);
+                server.send_message(&msg, &mut outputs, &app_tx);
+                server.store(Record::Clock(server.clock.clone()));
                 break;
             }
             Event::GetSnapshot => {
                 is_waiting_for_snapshot = true;
 
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    SnapshotRequest(server.app_id.to_owned()),
-                    server.clock.clone(),
-                );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                server.saved_messages.push(msg.clone());
+                let msg = server.new_message(SnapshotRequest(server.app_id.to_owned()));
+                server.send_message(&msg, &mut outputs, &app_tx);
+                server.save_message(msg);
 
                 // Set up timeout
                 let self_tx = self_tx.clone();
@@ -390,44 +1376,426 @@ pub fn run(
             }
             // Input from a distant app
             //-------------------------
-            Event::DistantInput(msg) => {
+            Event::RejectedFrame(link) => {
+                let count = server.rejected_frames.entry(link).or_insert(0);
+                *count += 1;
+                log::warn!(
+                    "rejected unauthenticated frame on link {}, {} so far",
+                    link,
+                    count
+                );
+                if *count == 1 {
+                    send_to_app(
+                        AppEvent::ServerError(format!(
+                            "Rejected an unauthenticated frame on link {}, \
+                             check that every app uses the same secret",
+                            link
+                        )),
+                        &app_tx,
+                    );
+                }
+            }
+            Event::DistantInput(link, msg) => {
                 if let Ok(mut msg) = Msg::from_str(&msg) {
                     // If we receive this message for the first time
-                    if server.sent_messages_ids.insert(msg.id.clone()) {
+                    if !server.sent_messages_ids.contains(&msg.id) {
+                        // Dropped messages are neither marked as seen nor merged into the
+                        // clock, so that a verifiable copy can still be caught up on
+                        match server.authenticate(&msg) {
+                            Authenticity::Forged => {
+                                // Neither delivered nor forwarded
+                                if server.dropped_ids.insert(msg.id) {
+                                    send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Dropped a message with an invalid signature claiming to come from {}",
+                                            msg.sender_id
+                                        )),
+                                        &app_tx,
+                                    );
+                                }
+                                continue;
+                            }
+                            // Messages from apps whose key is unknown are forwarded but not
+                            // delivered, except for the id challenge sent before any profile
+                            Authenticity::UnknownSender
+                                if !matches!(msg.header, IdProbe(_) | IdTaken(_)) =>
+                            {
+                                if server.dropped_ids.insert(msg.id) {
+                                    server.route_message(&msg, Some(link), &mut outputs, &app_tx);
+                                    send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Dropped an unverifiable message from {}",
+                                            msg.sender_id
+                                        )),
+                                        &app_tx,
+                                    );
+                                }
+                                continue;
+                            }
+                            _ => {}
+                        }
+
+                        server.sent_messages_ids.insert(msg.id);
                         server.increment_clock();
-                        server.receive_message(&mut msg, &mut output_file, &app_tx);
+                        server.receive_message(&mut msg, link, &mut outputs, &app_tx);
 
                         match &msg.header {
-                            Public(_) => {
-                                send_to_app(AppEvent::DistantMessage(msg), &app_tx);
+                            Public(_, _) => {
+                                let msg_id = msg.id;
+                                server.accept_public(msg, &app_tx);
+
+                                for revision in server.release_revisions(msg_id) {
+                                    server.accept_public(revision, &app_tx);
+                                }
+                            }
+                            Edit(target, _) | Delete(target) | Reaction(target, _) => {
+                                match server.check_revision(&msg, *target) {
+                                    Some(true) => server.accept_public(msg, &app_tx),
+                                    Some(false) => send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Dropped a change from {} to a message they did not send",
+                                            msg.sender_id
+                                        )),
+                                        &app_tx,
+                                    ),
+                                    // Applied once the original message arrives
+                                    None => server.pending_revisions.push(msg),
+                                }
                             }
                             Private(app_id, _) if *app_id == server.app_id => {
-                                send_to_app(AppEvent::DistantMessage(msg.clone()), &app_tx);
-                                server.saved_messages.push(msg);
+                                // Only messages the user can read are acknowledged
+                                if let Some(decrypted) = server.decrypt_private(&msg) {
+                                    send_to_app(AppEvent::DistantMessage(decrypted), &app_tx);
+                                    let ack = server
+                                        .new_message(Delivered(msg.sender_id.clone(), msg.id));
+                                    server.send_message(&ack, &mut outputs, &app_tx);
+                                } else {
+                                    send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Could not decrypt a private message from {}",
+                                            msg.sender_id
+                                        )),
+                                        &app_tx,
+                                    );
+                                }
+                                // Private messages are saved encrypted
+                                server.save_message(msg);
+                            }
+                            Group(_, members, _) if members.contains(&server.app_id) => {
+                                if let Some(decrypted) = server.decrypt_private(&msg) {
+                                    send_to_app(AppEvent::DistantMessage(decrypted), &app_tx);
+                                } else {
+                                    send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Could not decrypt a group message from {}",
+                                            msg.sender_id
+                                        )),
+                                        &app_tx,
+                                    );
+                                }
+                                server.save_message(msg);
+                            }
+                            // Kept for when the recipient comes back
+                            Private(app_id, _) if !server.peers.contains_key(app_id) => {
+                                server.mailbox.hold(&app_id.clone(), msg.clone());
+                            }
+                            Connection(profile) => {
+                                // Peers may introduce themselves before their connection
+                                // arrives, and another app using a known id would not
+                                // pass the signature check, so only our own id matters
+                                if msg.sender_id == server.app_id {
+                                    send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Id conflict: {} is already connected",
+                                            msg.sender_id
+                                        )),
+                                        &app_tx,
+                                    );
+                                }
+                                send_to_app(
+                                    AppEvent::ServerMessage(format!(
+                                        "{} ({}) joined",
+                                        profile.nickname, msg.sender_id
+                                    )),
+                                    &app_tx,
+                                );
+                                server.set_peer_profile(&msg.sender_id, profile, &app_tx);
+
+                                // Introduce ourselves to the newcomer
+                                let profile_update =
+                                    server.new_message(ProfileUpdate(server.profile()));
+                                server.send_message(&profile_update, &mut outputs, &app_tx);
+
+                                server.redeliver(&msg.sender_id, &mut outputs, &app_tx);
                             }
-                            Connection => {
+                            // Someone else is trying to join with our id, if we are
+                            // still probing ourselves the lowest nonce keeps the id
+                            IdProbe(nonce)
+                                if msg.sender_id == server.app_id
+                                    && server.id_probe.is_none_or(|own| own < *nonce) =>
+                            {
+                                let msg = server.new_message(IdTaken(*nonce));
+                                server.send_message(&msg, &mut outputs, &app_tx);
+                            }
+                            IdTaken(nonce) if server.id_probe == Some(*nonce) => {
+                                server.id_probe = None;
+                                if !server.config.rename_on_id_conflict {
+                                    let error = format!(
+                                        "The id {} is already used by another app, \
+                                         choose another one with --id",
+                                        server.app_id
+                                    );
+                                    send_to_app(AppEvent::Fatal(error.clone()), &app_tx);
+                                    return Err(error.into());
+                                }
+
+                                let suffix: String =
+                                    (0..4).map(|_| rng.sample(Alphanumeric)).collect();
+                                let app_id = format!("{}-{}", server.app_id, suffix);
+                                send_to_app(
+                                    AppEvent::ServerMessage(format!(
+                                        "The id {} is already in use, renamed to {}",
+                                        server.app_id, app_id
+                                    )),
+                                    &app_tx,
+                                );
+                                server.rename(app_id.clone());
                                 send_to_app(
-                                    AppEvent::ServerMessage(format!("{} joined", msg.sender_id)),
+                                    AppEvent::Renamed(app_id, server.nickname.clone()),
                                     &app_tx,
                                 );
+                                server.send_id_probe(&mut outputs, &app_tx, &self_tx);
+                            }
+                            ProfileUpdate(profile) => {
+                                server.set_peer_profile(&msg.sender_id, profile, &app_tx);
                             }
                             Disconnection => {
+                                server.router.forget(&msg.sender_id);
+                                let name = server
+                                    .peers
+                                    .remove(&msg.sender_id)
+                                    .unwrap_or_else(|| msg.sender_id.clone());
                                 send_to_app(
-                                    AppEvent::ServerMessage(format!("{} left", msg.sender_id)),
+                                    AppEvent::ServerMessage(format!("{} left", name)),
                                     &app_tx,
                                 );
                             }
                             SnapshotRequest(app_id) => {
-                                let msg_id: MsgId = rng.gen();
-                                server.sent_messages_ids.insert(msg_id.clone());
-                                server.increment_clock();
-                                let msg = Msg::new(
-                                    msg_id,
-                                    server.app_id.clone(),
-                                    SnapshotResponse(app_id.clone(), server.saved_messages.clone()),
-                                    server.clock.clone(),
+                                let msg = server.new_message(SnapshotResponse(
+                                    app_id.clone(),
+                                    server.saved_messages.clone(),
+                                ));
+                                server.send_message(&msg, &mut outputs, &app_tx);
+                            }
+                            SyncRequest(clock) => {
+                                let missing = server.missing_messages(clock);
+                                if !missing.is_empty() {
+                                    let mut profiles: HashMap<AppId, Profile> = missing
+                                        .iter()
+                                        .filter_map(|m| {
+                                            let profile = server.profiles.get(&m.sender_id)?;
+                                            Some((m.sender_id.clone(), profile.clone()))
+                                        })
+                                        .collect();
+                                    profiles.insert(server.app_id.clone(), server.profile());
+
+                                    let msg = server.new_message(SyncResponse(
+                                        msg.sender_id.clone(),
+                                        missing,
+                                        profiles,
+                                    ));
+                                    server.send_message(&msg, &mut outputs, &app_tx);
+                                }
+                            }
+                            SyncResponse(app_id, missing, profiles)
+                                if *app_id == server.app_id && server.backlog.is_some() =>
+                            {
+                                // Profiles of apps we have never met are trusted on first use
+                                for (app_id, profile) in profiles {
+                                    if !server.verifying_keys.contains_key(app_id) {
+                                        server.restore_profile(app_id, profile);
+                                        send_to_app(
+                                            AppEvent::PeerNickname(
+                                                app_id.clone(),
+                                                profile.nickname.clone(),
+                                            ),
+                                            &app_tx,
+                                        );
+                                    }
+                                }
+                                for missed in missing {
+                                    if server.authenticate(missed) != Authenticity::Verified {
+                                        log::warn!(
+                                            "dropped unverifiable backlog message {:?}",
+                                            missed
+                                        );
+                                    } else if server.sent_messages_ids.insert(missed.id) {
+                                        if let Some(backlog) = &mut server.backlog {
+                                            backlog.push(missed.clone());
+                                        }
+                                    }
+                                }
+                            }
+                            Redelivery(app_id, held, profile) if *app_id == server.app_id => {
+                                // Every app holding the message sends it, the first copy is
+                                // delivered and all of them are acknowledged
+                                if server.sent_messages_ids.insert(held.id) {
+                                    if !server.verifying_keys.contains_key(&held.sender_id) {
+                                        server.restore_profile(&held.sender_id, profile);
+                                        send_to_app(
+                                            AppEvent::PeerNickname(
+                                                held.sender_id.clone(),
+                                                profile.nickname.clone(),
+                                            ),
+                                            &app_tx,
+                                        );
+                                    }
+                                    if server.authenticate(held) != Authenticity::Verified {
+                                        send_to_app(
+                                            AppEvent::ServerError(format!(
+                                                "Dropped a delayed private message with an invalid signature claiming to come from {}",
+                                                held.sender_id
+                                            )),
+                                            &app_tx,
+                                        );
+                                        continue;
+                                    }
+                                    match server.decrypt_private(held) {
+                                        Some(decrypted) => send_to_app(
+                                            AppEvent::DistantMessage(decrypted),
+                                            &app_tx,
+                                        ),
+                                        None => send_to_app(
+                                            AppEvent::ServerError(format!(
+                                                "Could not decrypt a private message from {}",
+                                                held.sender_id
+                                            )),
+                                            &app_tx,
+                                        ),
+                                    }
+                                    server.save_message((**held).clone());
+                                }
+
+                                // Lets the sender know, and every holder forget the message,
+                                // unless it cannot be read. Acknowledgements are flooded
+                                // so that they reach all of them
+                                if server.decrypt_private(held).is_none() {
+                                    continue;
+                                }
+                                let ack =
+                                    server.new_message(Delivered(held.sender_id.clone(), held.id));
+                                server.send_message(&ack, &mut outputs, &app_tx);
+                            }
+                            Delivered(sender_id, msg_id) => {
+                                let was_held = server.mailbox.remove(*msg_id);
+                                if *sender_id == server.app_id {
+                                    send_to_app(
+                                        AppEvent::Receipt(*msg_id, Receipt::Delivered),
+                                        &app_tx,
+                                    );
+                                    // Delayed messages are worth a notice, others only get a marker
+                                    if was_held {
+                                        let name = server
+                                            .peers
+                                            .get(&msg.sender_id)
+                                            .cloned()
+                                            .unwrap_or_else(|| msg.sender_id.clone());
+                                        let text = server
+                                            .saved_messages
+                                            .iter()
+                                            .find(|saved| saved.id == *msg_id)
+                                            .and_then(|saved| server.decrypt_private(saved));
+                                        let notice = match text.map(|m| m.header) {
+                                            Some(Private(_, text)) => format!(
+                                                "Your private message to {} was delivered: {}",
+                                                name, text
+                                            ),
+                                            _ => format!(
+                                                "Your private message to {} was delivered",
+                                                name
+                                            ),
+                                        };
+                                        send_to_app(AppEvent::ServerMessage(notice), &app_tx);
+                                    }
+                                }
+                            }
+                            FileStart(recipient, description)
+                                if recipient.as_ref().is_none_or(|r| *r == server.app_id) =>
+                            {
+                                let info = match server.file_info(
+                                    &msg.sender_id,
+                                    recipient.is_some(),
+                                    description,
+                                ) {
+                                    Some(info) => info,
+                                    None => {
+                                        send_to_app(
+                                            AppEvent::ServerError(format!(
+                                                "Could not read the description of a file from {}",
+                                                msg.sender_id
+                                            )),
+                                            &app_tx,
+                                        );
+                                        continue;
+                                    }
+                                };
+                                if info.size > MAX_FILE_SIZE {
+                                    send_to_app(
+                                        AppEvent::ServerError(format!(
+                                            "Ignored {} from {}, files are limited to {} MiB",
+                                            info.name,
+                                            msg.sender_id,
+                                            MAX_FILE_SIZE / (1024 * 1024)
+                                        )),
+                                        &app_tx,
+                                    );
+                                    continue;
+                                }
+                                // Pieces may overtake the description on another route
+                                let download =
+                                    server.download(info.transfer_id, &msg.sender_id, &self_tx);
+                                if download.sender_id == msg.sender_id && download.info.is_none() {
+                                    download.info = Some(info.clone());
+                                    server.update_download(info.transfer_id, &app_tx);
+                                }
+                            }
+                            FileChunk(recipient, chunk)
+                                if recipient.as_ref().is_none_or(|r| *r == server.app_id) =>
+                            {
+                                let content = server.chunk_content(
+                                    &msg.sender_id,
+                                    recipient.is_some(),
+                                    chunk,
                                 );
-                                server.send_message(&msg, &mut output_file, &app_tx);
+                                let download =
+                                    server.download(chunk.transfer_id, &msg.sender_id, &self_tx);
+                                if download.sender_id != msg.sender_id {
+                                    log::warn!("dropped a file piece from {}", msg.sender_id);
+                                    continue;
+                                }
+                                match content {
+                                    Some(content) => {
+                                        download.add_chunk(chunk.index, content);
+                                        server.update_download(chunk.transfer_id, &app_tx);
+                                    }
+                                    None => {
+                                        server.downloads.remove(&chunk.transfer_id);
+                                        send_to_app(AppEvent::FileDone(chunk.transfer_id), &app_tx);
+                                        send_to_app(
+                                            AppEvent::ServerError(format!(
+                                                "A file from {} is corrupted, the transfer was aborted",
+                                                msg.sender_id
+                                            )),
+                                            &app_tx,
+                                        );
+                                    }
+                                }
+                            }
+                            Typing => {
+                                send_to_app(AppEvent::Typing(msg.sender_id.clone()), &app_tx);
+                            }
+                            Read(sender_id, msg_id) if *sender_id == server.app_id => {
+                                send_to_app(AppEvent::Receipt(*msg_id, Receipt::Read), &app_tx);
                             }
                             SnapshotResponse(app_id, _) if *app_id == server.app_id => {
                                 server.snapshot.add(msg);
@@ -454,3 +1822,179 @@ pub fn run(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Instant;
+
+    /// Runs a server reading from and writing to the given pipes of `dir`
+    fn start(
+        dir: &Path,
+        app_id: &str,
+        inputs: &[&str],
+        outputs: &[&str],
+    ) -> (mpsc::Sender<Event>, mpsc::Receiver<AppEvent>) {
+        let (app_tx, app_rx) = mpsc::channel();
+        let (server_tx, server_rx) = mpsc::channel();
+        let server = Server::new(app_id.to_owned(), app_id.to_owned(), Config::default());
+        let paths = |names: &[&str]| names.iter().map(|name| dir.join(name)).collect();
+        let (inputs, outputs) = (paths(inputs), paths(outputs));
+        thread::spawn(move || {
+            if let Err(e) = run(server, server_rx, app_tx, inputs, outputs) {
+                log::error!("{}", e);
+            }
+        });
+        (server_tx, app_rx)
+    }
+
+    /// Creates the pipes `names` in a new directory
+    fn pipes(name: &str, names: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("netchat-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for pipe in names {
+            let status = Command::new("mkfifo").arg(dir.join(pipe)).status().unwrap();
+            assert!(status.success());
+        }
+        dir
+    }
+
+    #[test]
+    fn clock_checkpoints() {
+        let dir = std::env::temp_dir().join(format!("netchat-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let clock = |dates: &[(&str, Date)]| {
+            Clock(
+                dates
+                    .iter()
+                    .map(|(id, date)| (id.to_string(), *date))
+                    .collect(),
+            )
+        };
+        let mut store = Store::open(&dir).unwrap();
+        store.append(&Record::Clock(clock(&[("alice", 3), ("bob", 7)])));
+        let msg = Msg::new(
+            1,
+            "bob".to_owned(),
+            Public(DEFAULT_CHANNEL.to_owned(), "I like trains !".to_owned()),
+            clock(&[("alice", 5), ("bob", 2)]),
+        );
+        store.append(&Record::Message(msg));
+
+        let (app_tx, _app_rx) = mpsc::channel();
+        let mut server = Server::new("alice".to_owned(), "alice".to_owned(), Config::default());
+        server.restore(Store::open(&dir).unwrap(), &app_tx);
+        assert_eq!(
+            server.clock,
+            clock(&[("alice", 5 + CLOCK_CHECKPOINT), ("bob", 7)])
+        );
+
+        // Events which are not saved only store the clock from time to time
+        let records = || {
+            fs::read_to_string(dir.join("store.jsonl"))
+                .unwrap()
+                .lines()
+                .count()
+        };
+        let before = records();
+        for _ in 0..CLOCK_CHECKPOINT {
+            server.new_message(Typing);
+        }
+        assert_eq!(records(), before + 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sync_dates() {
+        let (app_tx, _app_rx) = mpsc::channel();
+        let mut server = Server::new("alice".to_owned(), "alice".to_owned(), Config::default());
+        let dates = |bob: Date| Clock(vec![("bob".to_owned(), bob)].into_iter().collect());
+        let msg = Msg::new(
+            1,
+            "bob".to_owned(),
+            Public(DEFAULT_CHANNEL.to_owned(), "I like trains !".to_owned()),
+            dates(2),
+        );
+        server.accept_public(msg, &app_tx);
+
+        // Dates only merged into the clock are not claimed as seen
+        server.clock.merge(&dates(5));
+        assert_eq!(server.public_dates(), dates(2));
+        assert_eq!(server.missing_messages(&dates(1)).len(), 1);
+        assert!(server.missing_messages(&dates(2)).is_empty());
+    }
+
+    #[test]
+    fn backlog_releases_revisions() {
+        let (app_tx, app_rx) = mpsc::channel();
+        let mut server = Server::new("alice".to_owned(), "alice".to_owned(), Config::default());
+        let msg = |id, header| Msg::new(id, "bob".to_owned(), header, Clock(HashMap::new()));
+
+        // The deletion arrives live before the message it applies to is caught up on
+        server.pending_revisions.push(msg(2, Delete(1)));
+        server.backlog = Some(vec![msg(
+            1,
+            Public(DEFAULT_CHANNEL.to_owned(), "I like trains !".to_owned()),
+        )]);
+        server.deliver_backlog(&app_tx);
+
+        let ids: Vec<MsgId> = server.public_history.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        match app_rx.try_recv() {
+            Ok(AppEvent::Backlog(backlog)) => assert_eq!(backlog.len(), 2),
+            _ => panic!("the backlog was not delivered"),
+        }
+    }
+
+    #[test]
+    fn id_probe_through_relay() {
+        let dir = pipes("line", &["a2b", "b2a", "b2c", "c2b"]);
+
+        // alice - bob - newcomer, bob pins the key of alice when she connects
+        let _alice = start(&dir, "alice", &["b2a"], &["a2b"]);
+        let _bob = start(&dir, "bob", &["a2b", "c2b"], &["b2a", "b2c"]);
+        thread::sleep(Duration::from_secs(2));
+
+        // The probe of an app claiming the same id has to reach alice through bob
+        let (_, newcomer) = start(&dir, "alice", &["b2c"], &["c2b"]);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match newcomer.recv_timeout(timeout) {
+                Ok(AppEvent::Fatal(_)) => break,
+                Ok(_) => {}
+                Err(_) => panic!("the newcomer was not told that its id is taken"),
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn one_way_ring() {
+        let dir = pipes("ring", &["a2b", "b2c", "c2a"]);
+        let (alice, _alice) = start(&dir, "alice", &["c2a"], &["a2b"]);
+        let _bob = start(&dir, "bob", &["a2b"], &["b2c"]);
+        let (_, carol) = start(&dir, "carol", &["b2c"], &["c2a"]);
+        thread::sleep(Duration::from_secs(2));
+
+        // bob relays the message of alice on the link it did not come from
+        alice
+            .send(Event::UserPublicMessage(
+                DEFAULT_CHANNEL.to_owned(),
+                "I like trains !".to_owned(),
+            ))
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match carol.recv_timeout(timeout) {
+                Ok(AppEvent::DistantMessage(msg)) if msg.sender_id == "alice" => break,
+                Ok(_) => {}
+                Err(_) => panic!("the message of alice did not go round the ring"),
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Items held for a while, the oldest one being dropped when full
+/// and every one of them once it expires
+#[derive(Debug)]
+pub struct ExpiringQueue<T> {
+    capacity: usize,
+    ttl: Duration,
+    items: VecDeque<(Instant, T)>,
+}
+
+impl<T> ExpiringQueue<T> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        ExpiringQueue {
+            capacity,
+            ttl,
+            items: VecDeque::new(),
+        }
+    }
+
+    /// Holds `item`, the oldest one is dropped when full
+    pub fn push(&mut self, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back((Instant::now(), item));
+    }
+
+    /// Items still held, expired ones are dropped
+    pub fn iter(&mut self) -> impl Iterator<Item = &T> {
+        let ttl = self.ttl;
+        self.items
+            .retain(|(held_since, _)| held_since.elapsed() < ttl);
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    /// Removes and returns the items matching `f`, expired ones are dropped
+    pub fn take(&mut self, mut f: impl FnMut(&T) -> bool) -> Vec<T> {
+        let ttl = self.ttl;
+        let (taken, kept) = self
+            .items
+            .drain(..)
+            .filter(|(held_since, _)| held_since.elapsed() < ttl)
+            .partition(|(_, item)| f(item));
+        self.items = kept;
+        taken.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_bounds() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut queue = ExpiringQueue::new(2, day);
+        queue.push(1);
+        queue.push(2);
+        queue.push(3);
+
+        // The first item was dropped to make room for the third one
+        assert_eq!(queue.iter().cloned().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(queue.take(|item| *item == 3), vec![3]);
+        assert!(queue.take(|item| *item == 3).is_empty());
+        assert_eq!(queue.iter().count(), 1);
+
+        let mut queue = ExpiringQueue::new(2, Duration::from_secs(0));
+        queue.push(1);
+        assert_eq!(queue.iter().count(), 0);
+        assert!(queue.take(|_| true).is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+use crate::app::AppId;
+use std::collections::HashMap;
+
+/// Routing table learned from the observed traffic (reverse-path learning):
+/// the link on which a message from an app arrived first is the shortest
+/// way back to this app.
+///
+/// Links are numbered after the order of the input/output files given on
+/// the command line. With several links, the n-th input and the n-th output
+/// are connected to the same neighbour. A single input and output may be
+/// part of a one-way ring, the input then coming from another neighbour.
+#[derive(Debug, Default)]
+pub struct Router {
+    routes: HashMap<AppId, usize>,
+}
+
+impl Router {
+    /// Records that messages from `app_id` come in through `link`
+    pub fn learn(&mut self, app_id: &AppId, link: usize) {
+        self.routes.insert(app_id.to_owned(), link);
+    }
+
+    /// Forgets the route to a disconnected app
+    pub fn forget(&mut self, app_id: &AppId) {
+        self.routes.remove(app_id);
+    }
+
+    /// Link leading to `app_id`, if known
+    pub fn next_hop(&self, app_id: &AppId) -> Option<usize> {
+        self.routes.get(app_id).cloned()
+    }
+
+    /// Links on which a message should be sent to reach `dest`:
+    /// the next hop if the route is known, every link but the one the
+    /// message came from otherwise. A single link is never left out, as it
+    /// is not known to lead back to where the message came from
+    pub fn links(
+        &self,
+        dest: Option<&AppId>,
+        from: Option<usize>,
+        link_count: usize,
+    ) -> Vec<usize> {
+        let from = if link_count > 1 { from } else { None };
+        if let Some(link) = dest.and_then(|dest| self.next_hop(dest)) {
+            if Some(link) != from {
+                return vec![link];
+            }
+        }
+        (0..link_count).filter(|link| Some(*link) != from).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_learning() {
+        let mut router = Router::default();
+        let alice = "alice".to_owned();
+        let bob = "bob".to_owned();
+
+        // Unknown recipients are reached by flooding every other link
+        assert_eq!(router.links(Some(&alice), Some(1), 3), vec![0, 2]);
+        assert_eq!(router.links(None, None, 3), vec![0, 1, 2]);
+
+        router.learn(&alice, 2);
+        assert_eq!(router.links(Some(&alice), Some(0), 3), vec![2]);
+        assert_eq!(router.links(Some(&bob), Some(0), 3), vec![1, 2]);
+
+        // In a one-way ring, messages go on along the ring
+        assert_eq!(router.links(None, Some(0), 1), vec![0]);
+
+        router.forget(&alice);
+        assert_eq!(router.next_hop(&alice), None);
+    }
+}
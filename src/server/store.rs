@@ -0,0 +1,111 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::messages::{Msg, Profile};
+use super::Clock;
+use crate::app::AppId;
+
+/// What makes an app recognizable by the others across restarts
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Identity {
+    pub app_id: AppId,
+    pub nickname: String,
+    /// Hex encoded secret keys, see `Keys::to_secret_hex`
+    pub secret_keys: String,
+}
+
+/// Entry of the append-only message store
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Record {
+    /// Message sent or delivered to the user, private ones being stored encrypted
+    Message(Msg),
+    /// Vector clock, saved every few local events and on shutdown,
+    /// stored messages carry theirs
+    Clock(Clock),
+    /// Profile received from another app
+    Profile(AppId, Profile),
+}
+
+/// On-disk state of an app, kept in its data directory:
+/// * `identity.json` holds the app id and secret keys
+/// * `store.jsonl` holds one `Record` per line, only ever appended to
+pub struct Store {
+    dir: PathBuf,
+    records_file: File,
+    records: Vec<Record>,
+}
+
+impl Store {
+    /// Opens the data directory, creating it if needed, and reads back the records
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let records_path = dir.join("store.jsonl");
+
+        let mut records = Vec::new();
+        if records_path.exists() {
+            for line in BufReader::new(File::open(&records_path)?).lines() {
+                let line = line?;
+                match serde_json::from_str(&line) {
+                    Ok(record) => records.push(record),
+                    // Most likely a record truncated by a crash
+                    Err(e) => log::error!("Skipping invalid record `{}`: {}", line, e),
+                }
+            }
+        }
+
+        let records_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&records_path)?;
+
+        Ok(Store {
+            dir: dir.to_owned(),
+            records_file,
+            records,
+        })
+    }
+
+    pub fn identity(&self) -> Option<Identity> {
+        let identity = fs::read_to_string(self.dir.join("identity.json")).ok()?;
+        serde_json::from_str(&identity).ok()
+    }
+
+    pub fn save_identity(&self, identity: &Identity) {
+        // The identity holds secret keys, only the owner may read it
+        let written = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(self.dir.join("identity.json"))
+            .and_then(|mut file| {
+                let identity = serde_json::to_string_pretty(identity)?;
+                file.write_all(format!("{}\n", identity).as_bytes())
+            });
+        if let Err(e) = written {
+            log::error!("Could not save identity: {}", e);
+        }
+    }
+
+    /// Records read when the store was opened
+    pub fn take_records(&mut self) -> Vec<Record> {
+        mem::take(&mut self.records)
+    }
+
+    pub fn append(&mut self, record: &Record) {
+        let written = serde_json::to_string(record)
+            .map_err(io::Error::from)
+            .and_then(|record| {
+                self.records_file
+                    .write_all(format!("{}\n", record).as_bytes())
+            });
+        if let Err(e) = written {
+            log::error!("Could not append to the store: {}", e);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::app::AppId;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::messages::FileInfo;
+
+/// Size of the pieces files are sent in
+pub const CHUNK_SIZE: usize = 16 * 1024;
+/// Largest file that can be sent or received
+pub const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Time without any new piece after which a download is dropped
+pub const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Hex encoded SHA-256 digest of `data`
+pub fn checksum(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// File being received, its pieces may arrive before its description
+#[derive(Debug)]
+pub struct Download {
+    pub sender_id: AppId,
+    pub info: Option<FileInfo>,
+    chunks: HashMap<u32, Vec<u8>>,
+    /// Date of the last piece received
+    updated: Instant,
+}
+
+impl Download {
+    pub fn new(sender_id: AppId) -> Self {
+        Download {
+            sender_id,
+            info: None,
+            chunks: HashMap::new(),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Keeps a piece of the file, unless it lies beyond the announced
+    /// or the largest allowed size
+    pub fn add_chunk(&mut self, index: u32, data: Vec<u8>) {
+        let chunks = match &self.info {
+            Some(info) => info.chunks as u64,
+            None => MAX_FILE_SIZE / CHUNK_SIZE as u64,
+        };
+        if (index as u64) < chunks {
+            self.chunks.insert(index, data);
+            self.updated = Instant::now();
+        }
+    }
+
+    /// Time elapsed since the last piece was received, or the download started
+    pub fn idle(&self) -> Duration {
+        self.updated.elapsed()
+    }
+
+    /// Number of pieces received
+    pub fn received(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Content of the file once every piece was received,
+    /// fails if it does not match the announced size or checksum
+    pub fn assemble(&self) -> Option<Result<Vec<u8>, String>> {
+        let info = self.info.as_ref()?;
+        if self.chunks.len() < info.chunks as usize {
+            return None;
+        }
+        let mut data = Vec::with_capacity(info.size as usize);
+        for index in 0..info.chunks {
+            match self.chunks.get(&index) {
+                Some(chunk) => data.extend_from_slice(chunk),
+                None => return None,
+            }
+        }
+        if data.len() as u64 != info.size || checksum(&data) != info.checksum {
+            return Some(Err(format!("{} is corrupted", info.name)));
+        }
+        Some(Ok(data))
+    }
+}
+
+/// Writes a received file to `dir` under its own name, without the path it may contain,
+/// a numbered suffix is added rather than overwriting an existing file
+pub fn save(dir: &Path, name: &str, data: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = Path::new(name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download");
+    let mut path = dir.join(name);
+    let mut suffix = 1;
+    while path.exists() {
+        path = dir.join(format!("{}.{}", name, suffix));
+        suffix += 1;
+    }
+    fs::write(&path, data)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_reassembly() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 42).map(|i| i as u8).collect();
+        let info = FileInfo {
+            transfer_id: 1,
+            name: "trains.log".to_owned(),
+            size: data.len() as u64,
+            chunks: 3,
+            checksum: checksum(&data),
+        };
+
+        let mut download = Download::new("asdasdw".to_owned());
+        assert!(download.idle() < TRANSFER_TIMEOUT);
+        // Pieces may arrive in any order, before the description
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate().rev() {
+            assert_eq!(download.assemble(), None);
+            download.add_chunk(index as u32, chunk.to_vec());
+        }
+        assert_eq!(download.assemble(), None);
+        download.info = Some(info.clone());
+        assert_eq!(download.assemble(), Some(Ok(data.clone())));
+
+        download.add_chunk(1, vec![0; CHUNK_SIZE]);
+        assert!(download.assemble().expect("missing pieces").is_err());
+    }
+}